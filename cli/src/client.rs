@@ -0,0 +1,142 @@
+//! JSON-RPC client commands (`txsched schedule`, `txsched cancel` and `txsched status`).
+
+use std::fs;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustc_hex::{FromHex, ToHex};
+use transaction_scheduler::client::Client;
+use transaction_scheduler::types::{Condition, TransactionId};
+
+use Args;
+
+/// Executes client subcommand against the scheduler at given URL.
+///
+/// `chain_id` is passed when scheduling (required for transactions without replay protection on multi-chain schedulers).
+pub fn execute(args: &Args, url: &str, chain_id: Option<u64>) -> Result<String, String> {
+    let url = match args.flag_api_key {
+        Some(ref key) => format!("{}/?apiKey={}", url.trim_right_matches('/'), percent_encode(key)),
+        None => url.to_owned(),
+    };
+    let client = Client::new(&url).map_err(|e| format!("Unable to connect to {}: {:?}", url, e))?;
+
+    if args.cmd_schedule {
+        let condition = condition(args)?;
+        let transaction = transaction(args.arg_transaction.as_ref().map(String::as_str).unwrap_or(""))?;
+        let id = client.schedule(&condition, transaction.into(), chain_id).map_err(|e| format!("Error scheduling transaction: {:?}", e))?;
+        Ok(describe(&id))
+    } else if args.cmd_cancel {
+        let id = id(args)?;
+        client.cancel(&id).map_err(|e| format!("Error cancelling transaction: {:?}", e))?;
+        Ok(format!("Cancelled.\n{}", describe(&id)))
+    } else if args.cmd_status {
+        let id = id(args)?;
+        let status = client.status(&id).map_err(|e| format!("Error fetching status: {:?}", e))?;
+        Ok(format!("status: {:?}\n{}", status, describe(&id)))
+    } else {
+        Err("Unknown client command.".into())
+    }
+}
+
+/// Returns id and its decoded fields.
+fn describe(id: &TransactionId) -> String {
+    let target = if id.is_timestamp {
+        format!("time: {} ms ({} s)", id.num, id.num / 1000)
+    } else {
+        format!("block: {}", id.num)
+    };
+
+    let chain = match id.chain_id {
+        Some(chain_id) => format!("\nchain: {}", chain_id),
+        None => String::new(),
+    };
+
+    format!(
+        "id: 0x{}\n{}\nimmediate: {}\nhash: 0x{}{}",
+        id.to_bytes().into_vec().to_hex(), target, id.immediate, id.hash[..].to_hex(), chain,
+    )
+}
+
+fn id(args: &Args) -> Result<TransactionId, String> {
+    let id = args.arg_id.as_ref().map(String::as_str).unwrap_or("");
+    let bytes: Vec<u8> = id.trim_left_matches("0x").from_hex().map_err(|e| format!("Invalid id {}: {}", id, e))?;
+    TransactionId::from_bytes(bytes.into()).ok_or_else(|| format!("Invalid id: {}", id))
+}
+
+fn condition(args: &Args) -> Result<Condition, String> {
+    match (args.flag_block, args.flag_time, args.flag_in.as_ref()) {
+        (Some(block), None, None) => Ok(Condition::Number(block)),
+        (None, Some(time), None) => Ok(Condition::Timestamp(time)),
+        (None, None, Some(delay)) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| format!("Invalid system time: {}", e))?;
+            let now = now.as_secs() * 1000 + u64::from(now.subsec_nanos()) / 1_000_000;
+            now.checked_add(delay_millis(delay)?)
+                .map(Condition::TimestampMs)
+                .ok_or_else(|| format!("Delay is too long: {}", delay))
+        },
+        _ => Err("Exactly one of --block, --time or --in has to be specified.".into()),
+    }
+}
+
+/// Parses delay like `90s`, `10m`, `2h` or `1d` (seconds by default).
+fn delay_millis(delay: &str) -> Result<u64, String> {
+    let split = delay.find(|c: char| !c.is_digit(10)).unwrap_or(delay.len());
+    let (value, unit) = delay.split_at(split);
+    let value: u64 = value.parse().map_err(|_| format!("Invalid delay: {}", delay))?;
+    let multiplier = match unit {
+        "ms" => 1,
+        "" | "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return Err(format!("Invalid delay unit: {}. Use ms, s, m, h or d.", unit)),
+    };
+    value.checked_mul(multiplier).ok_or_else(|| format!("Delay is too long: {}", delay))
+}
+
+/// Encodes given query parameter value (all characters except unreserved ones are escaped).
+fn percent_encode(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+/// Reads hex-encoded RLP given directly or from a file (hex or binary).
+fn transaction(transaction: &str) -> Result<Vec<u8>, String> {
+    if transaction.starts_with("0x") {
+        return transaction[2..].from_hex().map_err(|e| format!("Invalid transaction RLP: {}", e));
+    }
+
+    let mut content = Vec::new();
+    fs::File::open(transaction)
+        .and_then(|mut file| file.read_to_end(&mut content))
+        .map_err(|e| format!("Unable to read transaction from {}: {}", transaction, e))?;
+
+    let hex = String::from_utf8(content.clone()).ok()
+        .and_then(|hex| hex.trim().trim_left_matches("0x").from_hex().ok());
+    Ok(hex.unwrap_or(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delay_millis, percent_encode};
+
+    #[test]
+    fn should_parse_delays() {
+        assert_eq!(delay_millis("500ms"), Ok(500));
+        assert_eq!(delay_millis("90"), Ok(90_000));
+        assert_eq!(delay_millis("10m"), Ok(600_000));
+        assert_eq!(delay_millis("2h"), Ok(7_200_000));
+        assert_eq!(delay_millis("1d"), Ok(86_400_000));
+        assert!(delay_millis("10y").is_err());
+        assert!(delay_millis("m").is_err());
+        assert!(delay_millis("18446744073709551615d").is_err());
+    }
+
+    #[test]
+    fn should_encode_api_keys() {
+        assert_eq!(percent_encode("key-1_a.b~"), "key-1_a.b~");
+        assert_eq!(percent_encode("a+b/c=&d e"), "a%2Bb%2Fc%3D%26d%20e");
+    }
+}
@@ -0,0 +1,267 @@
+//! Offline database commands (`txsched db ...`).
+//!
+//! The commands operate directly on `rpc.db_path` and refuse to run while the scheduler is running
+//! (the database directories are locked). Only `import` and `compact` modify the database.
+//!
+//! API keys are listed and exported as key ids (see `ApiKeys::key_id`), never as the secrets.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::fs;
+
+use rustc_hex::{FromHex, ToHex};
+use serde_json;
+use transaction_scheduler::ApiKeys;
+use transaction_scheduler::database::Database;
+use transaction_scheduler::types::{Bytes, Transaction, TransactionId, H256};
+
+use config::Config;
+use Args;
+
+const BLOCK: &str = "block";
+const TIME: &str = "time";
+
+/// Exported transaction.
+///
+/// `hash` and `sender` are informative, they are verified against the RLP during import.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    /// `block` or `time` queue.
+    queue: String,
+    /// Block number or timestamp in milliseconds.
+    target: u64,
+    #[serde(default)]
+    hash: String,
+    #[serde(default)]
+    sender: String,
+    rlp: Bytes,
+    api_key: Option<String>,
+}
+
+impl Record {
+    fn new(queue: &str, target: u64, transaction: &Transaction) -> Self {
+        Record {
+            queue: queue.into(),
+            target,
+            hash: hex(&**transaction.hash()),
+            sender: hex(&**transaction.sender()),
+            rlp: transaction.rlp().to_vec().into(),
+            // Records of previous versions are normalized to key ids when read.
+            api_key: transaction.api_key().map(|key| ApiKeys::normalize_id(key.to_owned())),
+        }
+    }
+}
+
+struct Databases {
+    block: Database,
+    time: Database,
+}
+
+impl Databases {
+    fn open(config: &Config, read_only: bool) -> Result<Self, String> {
+        let open = |path: &str| if read_only {
+            Database::open_read_only(path)
+        } else {
+            Database::open(path, config.verification.max_txs_per_sender)
+        }.map_err(|e| format!("Error opening database at {}: {}", path, e));
+
+        Ok(Databases {
+            block: open(&config.rpc.db_path)?,
+            time: open(&format!("{}/time_ms/", config.rpc.db_path))?,
+        })
+    }
+
+    fn queues(&self) -> Vec<(&'static str, &Database)> {
+        vec![(BLOCK, &self.block), (TIME, &self.time)]
+    }
+
+    fn queue(&self, queue: &str) -> Result<&Database, String> {
+        match queue {
+            BLOCK => Ok(&self.block),
+            TIME => Ok(&self.time),
+            _ => Err(format!("Unknown queue: {}. Expected `{}` or `{}`.", queue, BLOCK, TIME)),
+        }
+    }
+
+    fn records(&self) -> Result<Vec<Record>, String> {
+        let mut records = Vec::new();
+        for (queue, db) in self.queues() {
+            let transactions = db.transactions().map_err(|e| format!("Error reading {} queue: {:?}", queue, e))?;
+            records.extend(transactions.iter().map(|&(target, ref tx)| Record::new(queue, target, tx)));
+        }
+        Ok(records)
+    }
+}
+
+/// Executes `db` subcommand.
+pub fn execute(args: &Args, config: &Config) -> Result<String, String> {
+    let dbs = Databases::open(config, !args.cmd_import && !args.cmd_compact)?;
+
+    if args.cmd_list {
+        list(&dbs, args)
+    } else if args.cmd_show {
+        show(&dbs, args.arg_id.as_ref().map(String::as_str).unwrap_or(""))
+    } else if args.cmd_export {
+        export(&dbs, &args.flag_format, args.flag_output.as_ref())
+    } else if args.cmd_import {
+        import(&dbs, args.arg_file.as_ref().map(String::as_str).unwrap_or("-"))
+    } else if args.cmd_verify {
+        verify(&dbs)
+    } else if args.cmd_compact {
+        compact(&dbs)
+    } else {
+        Err("Unknown db command.".into())
+    }
+}
+
+fn list(dbs: &Databases, args: &Args) -> Result<String, String> {
+    if let Some(ref queue) = args.flag_queue {
+        dbs.queue(queue)?;
+    }
+    let sender = args.flag_sender.as_ref().map(|sender| normalize(sender));
+
+    let lines = dbs.records()?.into_iter()
+        .filter(|record| args.flag_queue.as_ref().map_or(true, |queue| *queue == record.queue))
+        .filter(|record| args.flag_target.map_or(true, |target| target == record.target))
+        .filter(|record| sender.as_ref().map_or(true, |sender| *sender == normalize(&record.sender)))
+        .map(|record| format!(
+            "{}\t{}\t{}\t{}\t{}",
+            record.queue, record.target, record.hash, record.sender, record.api_key.unwrap_or_default(),
+        ))
+        .collect::<Vec<_>>();
+
+    Ok(format!("{}\nFound {} transactions.", lines.join("\n"), lines.len()).trim().to_owned())
+}
+
+fn show(dbs: &Databases, id: &str) -> Result<String, String> {
+    let bytes: Vec<u8> = normalize(id).from_hex().map_err(|e| format!("Invalid id {}: {}", id, e))?;
+    // Accept both transaction ids returned by `scheduleTransaction` and transaction hashes.
+    let (queue, target, hash) = if bytes.len() == 32 {
+        (None, None, H256::from_slice(&bytes))
+    } else {
+        let id = TransactionId::from_bytes(bytes.into()).ok_or_else(|| format!("Invalid id: {}", id))?;
+        (Some(if id.is_timestamp { TIME } else { BLOCK }), Some(id.num), id.hash)
+    };
+
+    let hash = hex(&*hash);
+    let record = dbs.records()?.into_iter()
+        .find(|record| {
+            record.hash == hash
+                && queue.map_or(true, |queue| queue == record.queue)
+                && target.map_or(true, |target| target == record.target)
+        })
+        .ok_or_else(|| format!("Transaction {} not found.", hash))?;
+
+    serde_json::to_string_pretty(&record).map_err(|e| e.to_string())
+}
+
+fn export(dbs: &Databases, format: &str, output: Option<&String>) -> Result<String, String> {
+    if format != "json" {
+        return Err(format!("Unsupported export format: {}. Only `json` is supported.", format));
+    }
+
+    let records = dbs.records()?;
+    let json = serde_json::to_string_pretty(&records).map_err(|e| e.to_string())?;
+    match output {
+        None => Ok(json),
+        Some(path) => {
+            fs::File::create(path)
+                .and_then(|mut file| file.write_all(json.as_bytes()))
+                .map_err(|e| format!("Unable to write {}: {}", path, e))?;
+            Ok(format!("Exported {} transactions to {}.", records.len(), path))
+        },
+    }
+}
+
+fn import(dbs: &Databases, path: &str) -> Result<String, String> {
+    let mut content = String::new();
+    let read = if path == "-" {
+        io::stdin().read_to_string(&mut content)
+    } else {
+        fs::File::open(path).and_then(|mut file| file.read_to_string(&mut content))
+    };
+    read.map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let records: Vec<Record> = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid import file: {}", e))?;
+
+    let mut existing = dbs.records()?.into_iter().map(|record| record.hash).collect::<HashSet<_>>();
+    let (mut imported, mut skipped) = (0, 0);
+    for record in records {
+        let result = dbs.queue(&record.queue)
+            .and_then(|db| import_record(db, &record, &existing).map(|tx| (db, tx)))
+            .and_then(|(db, tx)| {
+                let hash = hex(&**tx.hash());
+                db.insert(record.target, tx).map(|_| hash).map_err(|e| format!("{:?}", e))
+            });
+        match result {
+            Ok(hash) => {
+                imported += 1;
+                existing.insert(hash);
+            },
+            Err(err) => {
+                warn!("Skipping transaction {}: {}", record.hash, err);
+                skipped += 1;
+            },
+        }
+    }
+
+    for (queue, db) in dbs.queues() {
+        db.flush().map_err(|e| format!("Error flushing {} queue: {:?}", queue, e))?;
+    }
+    Ok(format!("Imported {} transactions, skipped {}.", imported, skipped))
+}
+
+fn import_record(db: &Database, record: &Record, existing: &HashSet<String>) -> Result<Transaction, String> {
+    let transaction = Transaction::decode(&record.rlp.0)?.with_api_key(record.api_key.clone());
+    let (hash, sender) = (hex(&**transaction.hash()), hex(&**transaction.sender()));
+    if !record.hash.is_empty() && normalize(&record.hash) != normalize(&hash) {
+        return Err(format!("Hash doesn't match the transaction ({}).", hash));
+    }
+    if !record.sender.is_empty() && normalize(&record.sender) != normalize(&sender) {
+        return Err(format!("Sender doesn't match the transaction ({}).", sender));
+    }
+    if existing.contains(&hash) {
+        return Err("Already scheduled.".into());
+    }
+    if !db.sender_allowed(transaction.sender()) {
+        return Err(format!("Sender {} has too many transactions.", sender));
+    }
+    Ok(transaction)
+}
+
+fn verify(dbs: &Databases) -> Result<String, String> {
+    let mut issues = Vec::new();
+    let mut checked = 0;
+    for (queue, db) in dbs.queues() {
+        checked += db.stats().transactions;
+        let found = db.verify().map_err(|e| format!("Error verifying {} queue: {:?}", queue, e))?;
+        issues.extend(found.into_iter().map(|issue| format!("{}: {}", queue, issue)));
+    }
+
+    if issues.is_empty() {
+        Ok(format!("Database is consistent. Checked {} transactions.", checked))
+    } else {
+        Err(format!("Found {} issues (run `txsched db compact` to remove them):\n{}", issues.len(), issues.join("\n")))
+    }
+}
+
+/// Removes invalid records, files of submitted transactions are kept for `rpc.db_retention_hours`.
+fn compact(dbs: &Databases) -> Result<String, String> {
+    let mut lines = Vec::new();
+    for (queue, db) in dbs.queues() {
+        let removed = db.compact().map_err(|e| format!("Error compacting {} queue: {:?}", queue, e))?;
+        lines.extend(removed.into_iter().map(|issue| format!("Removed {}: {}", queue, issue)));
+    }
+
+    lines.push(format!("Removed {} invalid records.", lines.len()));
+    Ok(lines.join("\n"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", bytes.to_hex())
+}
+
+fn normalize(hex: &str) -> String {
+    hex.trim_left_matches("0x").to_lowercase()
+}
@@ -5,30 +5,107 @@ extern crate serde_derive;
 
 extern crate docopt;
 extern crate env_logger;
+extern crate futures;
+extern crate parking_lot;
+extern crate rustc_hex;
+extern crate serde_json;
+extern crate signal_hook;
 extern crate toml;
 extern crate transaction_scheduler;
 
+#[cfg(test)]
+extern crate tempdir;
+
+mod client;
 mod config;
+mod db;
+mod reload;
+mod sign;
 
-use std::{env, thread, path, fs};
+use std::collections::{HashMap, HashSet};
+use std::{env, thread, path, process, fs, time};
 use std::io::Read;
 use std::sync::Arc;
 
 use docopt::Docopt;
-use transaction_scheduler::{blockchain, database, server, submitter, TransportType, Options};
+use futures::sync::mpsc;
+use signal_hook::iterator::Signals;
+use transaction_scheduler::transport::Endpoint;
+use transaction_scheduler::types::{BlockNumber, U256};
+use transaction_scheduler::{blockchain, chain, database, gas_price, policy, rules, schedule, server, submitter, Options, SharedOptions, ApiKeys, ApiKey, Limit};
 
 const USAGE: &str = r#"
 Signed Transaction Scheduler
     Copyright 2017 Parity Technologies (UK) Limited
 
 Usage:
-    txsched [options]
+    txsched [--set KEY=VALUE]... [options]
+    txsched config print [--set KEY=VALUE]... [options]
+    txsched db list [--queue QUEUE] [--target NUM] [--sender ADDR] [options]
+    txsched db show <id> [options]
+    txsched db export [--format FORMAT] [--output FILE] [options]
+    txsched db import [<file>] [options]
+    txsched db verify [options]
+    txsched db compact [options]
+    txsched schedule (--block NUM | --time TIME | --in DELAY) <transaction> [--rpc URL] [--api-key KEY] [options]
+    txsched cancel <id> [--rpc URL] [--api-key KEY] [options]
+    txsched status <id> [--rpc URL] [--api-key KEY] [options]
+    txsched sign (--key KEY | --keystore FILE [--password FILE]) [--to ADDR] [--value WEI] [--data HEX] [--gas GAS] [--gas-price WEI] [--nonce NONCE] [--block NUM | --time TIME] [options]
     txsched -h | --help
 
 Options:
-    --config FILE       Specify config file to use [default: config.toml].
+    --config FILE       Specify config file to use (defaults to `config.toml` if it exists).
+    --set KEY=VALUE     Override config value, e.g. `rpc.port=3002` (takes precedence over
+                        `TXSCHED_<SECTION>_<KEY>` environment variables, config file and defaults).
+    --chain CHAIN       Chain (name or chain id from `[[chains]]`) of database and signing commands,
+                        chain id of `schedule` (only needed for transactions without replay protection).
     -l, --log LVL       Define a log level (info, trace, debug) [default: Info].
     -h, --help          Display help message and exit.   
+
+Config commands:
+    print               Print effective configuration with the source of every value.
+
+Database commands (run against `rpc.db_path`, refused while the scheduler is running):
+    list                List scheduled transactions.
+    show                Show transaction with given id (as returned by `scheduleTransaction`) or hash.
+    export              Export all scheduled transactions.
+    import              Import transactions exported before (reads stdin by default).
+    verify              Check that all records are readable and match the transactions.
+    compact             Remove invalid records.
+
+Database options:
+    --queue QUEUE       List only `block` or `time` queue.
+    --target NUM        List only transactions scheduled for given block or timestamp (ms).
+    --sender ADDR       List only transactions of given sender.
+    --format FORMAT     Export format [default: json].
+    --output FILE       Write exported transactions to a file.
+
+Client commands (connect to a running scheduler):
+    schedule            Schedule signed transaction (hex RLP or a file containing it).
+    cancel              Cancel scheduled transaction with given id.
+    status              Show status of scheduled transaction with given id.
+
+Client options:
+    --block NUM         Submit at given block number (`sign` checks gas against the fork active then).
+    --time TIME         Submit at given unix timestamp (seconds).
+    --in DELAY          Submit after given delay (e.g. 90s, 10m, 2h, 1d).
+    --rpc URL           Scheduler JSON-RPC endpoint (defaults to `rpc.interface` and `rpc.port` from config).
+    --api-key KEY       API key to send with requests.
+
+Signing commands (print hex RLP accepted by `schedule`):
+    sign                Build and sign a transaction for `verification.chain_id` (or the chain of `nodes.blockchain`)
+                        and check it the same way the scheduler does.
+
+Signing options:
+    --key KEY           Hex-encoded secret key or a file containing it.
+    --keystore FILE     Encrypted JSON key file.
+    --password FILE     File containing the key file password.
+    --to ADDR           Recipient (omit to create a contract).
+    --value WEI         Transferred value (decimal or 0x-prefixed hex) [default: 0].
+    --data HEX          Transaction data [default: 0x].
+    --gas GAS           Gas limit (defaults to intrinsic gas for plain transfers, estimated by `nodes.blockchain` otherwise).
+    --gas-price WEI     Gas price (defaults to the price of `nodes.blockchain`, at least `verification.min_gas_price`).
+    --nonce NONCE       Sender nonce (defaults to the nonce in `nodes.blockchain` state).
 "#;
 
 #[derive(Debug, Deserialize)]
@@ -41,14 +118,54 @@ enum Logger {
 
 #[derive(Debug, Deserialize)]
 pub struct Args {
-    flag_config: path::PathBuf,
+    cmd_config: bool,
+    cmd_print: bool,
+    cmd_db: bool,
+    cmd_list: bool,
+    cmd_show: bool,
+    cmd_export: bool,
+    cmd_import: bool,
+    cmd_verify: bool,
+    cmd_compact: bool,
+    cmd_schedule: bool,
+    cmd_cancel: bool,
+    cmd_status: bool,
+    cmd_sign: bool,
+    arg_id: Option<String>,
+    arg_file: Option<String>,
+    arg_transaction: Option<String>,
+    flag_config: Option<path::PathBuf>,
+    flag_set: Vec<String>,
+    flag_chain: Option<String>,
     flag_log: Logger,
+    flag_queue: Option<String>,
+    flag_target: Option<u64>,
+    flag_sender: Option<String>,
+    flag_format: String,
+    flag_output: Option<String>,
+    flag_block: Option<u64>,
+    flag_time: Option<u64>,
+    flag_in: Option<String>,
+    flag_rpc: Option<String>,
+    flag_api_key: Option<String>,
+    flag_key: Option<String>,
+    flag_keystore: Option<String>,
+    flag_password: Option<String>,
+    flag_to: Option<String>,
+    flag_value: String,
+    flag_data: String,
+    flag_gas: Option<String>,
+    flag_gas_price: Option<String>,
+    flag_nonce: Option<String>,
 }
 
 fn main() {
     match execute(env::args()) {
         Ok(msg) => println!("{}", msg),
-        Err(err) => eprintln!("{}", err),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
     }
 }
 
@@ -73,87 +190,604 @@ fn execute<S, I>(command: I) -> Result<String, String> where
     });
     let _ = builder.init();
 
-    // Read config file
-    let mut file = fs::File::open(&args.flag_config)
-        .map_err(|e| format!("Unable to open config file at {}: {}", args.flag_config.display(), e))?;
-    let mut content = String::new();
-    file.read_to_string(&mut content)
-        .map_err(|e| format!("Error reading config: {}", e))?;
-    let config: config::Config = toml::from_str(&content)
-        .map_err(|e| format!("Invalid config: {}", e))?;
-
-    // Construct options
-    let options = Options {
-        chain_id: config.verification.chain_id,
-        max_gas: config.verification.max_gas,
-        min_gas_price: config.verification.min_gas_price,
-        max_schedule_block: config.verification.max_schedule_block,
-        max_schedule_seconds: config.verification.max_schedule_seconds,
-        strict_nonce: config.verification.strict_nonce,
-        rpc_listen_address: format!("{}:{}", config.rpc.interface, config.rpc.port).parse().map_err(|e| format!("Invalid interface or port: {}", e))?,
-        rpc_server_threads: config.rpc.server_threads,
-        processing_threads: config.rpc.processing_threads,
-    };
+    // Client commands don't need config if the endpoint is given.
+    let is_client = args.cmd_schedule || args.cmd_cancel || args.cmd_status;
+    if let (true, Some(url)) = (is_client, args.flag_rpc.as_ref()) {
+        let chain_id = match args.flag_chain {
+            Some(ref chain) => Some(chain.parse().map_err(|_| format!("Invalid chain id: {} (chain names require config)", chain))?),
+            None => None,
+        };
+        return client::execute(&args, url, chain_id);
+    }
+
+    // Read config (defaults, config file, environment and `--set` flags).
+    let loader = config::Loader::new(args.flag_config.clone(), args.flag_set.clone());
+    if args.cmd_config {
+        let layers = loader.layers()?;
+        return match layers.config() {
+            Ok(_) => Ok(layers.print()),
+            Err(err) => Err(format!("{}\n\n{}", layers.print(), err)),
+        };
+    }
+    let config = loader.load()?;
+
+    let chain = args.flag_chain.as_ref().map(String::as_str);
+
+    // Offline database commands.
+    if args.cmd_db {
+        let (_, config) = config.chain(chain)?;
+        return db::execute(&args, &config);
+    }
+
+    if args.cmd_sign {
+        let (_, config) = config.chain(chain)?;
+        return sign::execute(&args, &config);
+    }
+
+    if is_client {
+        let url = format!("http://{}:{}", config.rpc.interface, config.rpc.port);
+        let chain_id = match chain {
+            Some(chain) => match chain.parse::<u64>() {
+                Ok(chain_id) => Some(chain_id),
+                Err(_) => Some(config.chain(Some(chain))?.1.verification.chain_id
+                    .ok_or_else(|| format!("Chain {} has no `verification.chain_id` configured, pass the chain id instead.", chain))?),
+            },
+            None => None,
+        };
+        return client::execute(&args, &url, chain_id);
+    }
+
+    // Start the blockchain cache and open the databases of every chain.
+    let chains = config.chains()?;
+    if config.chains.is_some() {
+        check_single_chain_database(&config.rpc.db_path)?;
+    }
+    let mut services: Vec<ChainServices> = Vec::new();
+    for &(ref name, ref chain_config) in &chains {
+        let chain = match config.chains {
+            Some(_) => start_chain(name, chain_config).map_err(|e| format!("Chain {}: {}", name, e))?,
+            None => start_chain(name, chain_config)?,
+        };
+        if let Some(other) = services.iter().find(|other| other.chain.chain_id == chain.chain.chain_id) {
+            return Err(format!("Chains {} and {} are both connected to chain id {}.", other.name, name, chain.chain.chain_id));
+        }
+        info!("Serving chain {} (chain id {}).", name, chain.chain.chain_id);
+        services.push(chain);
+    }
+
+    // Handle termination and reload signals (registered before any request is accepted).
+    let signals = Signals::new(&[signal_hook::SIGINT, signal_hook::SIGTERM, signal_hook::SIGHUP])
+        .map_err(|e| format!("Error registering signal handlers: {}", e))?;
 
-    let blockchain_node_address = config.nodes.blockchain.clone();
-    // A certifier contract query interface.
-    let certifier = match config.verification.certifier.as_ref().map(|x| x.parse()) {
-        None => None,
-        Some(Ok(address)) => Some(address),
-        Some(Err(err)) => return Err(format!("Unable to parse certifier address: {}", err)),
+    // Config reload (on SIGHUP or `admin_reloadConfig`).
+    let targets = services.iter().map(|service| reload::Target {
+        name: service.name.clone(),
+        chain_id: service.chain.chain_id,
+        transactions: service.transactions.clone(),
+        options: service.chain.options.clone(),
+        control: service.chain.control.clone(),
+    }).collect();
+    let reloader = Arc::new(reload::Reloader::new(loader, config.clone(), targets));
+    let reload = {
+        let reloader = reloader.clone();
+        Arc::new(move || reloader.reload()) as Arc<server::Reload>
     };
+
+    // JSON-RPC servers verifying and accepting requests (routed by chain id).
+    let server = server::start(services.iter().map(|service| service.chain.clone()).collect(), Some(reload))
+        .map_err(|e| e.to_string())?;
+
+    // spawn submitters and blockchain updaters
+    let controls = services.iter().map(|service| service.chain.control.clone()).collect::<Vec<_>>();
+    let mut databases = Vec::new();
+    let mut handles = Vec::new();
+    for ChainServices { name, chain, blockchain_nodes, transaction_nodes, updater, listener, garbage_collector, recheck, submit_earlier, .. } in services {
+        handles.push(garbage_collector);
+        databases.push(chain.block_db.clone());
+        databases.push(chain.timestamp_db.clone());
+        databases.push(chain.block_underpriced.clone());
+        databases.push(chain.timestamp_underpriced.clone());
+
+        let transactions = transaction_nodes.clone();
+        let control = chain.control.clone();
+        let blockchain = chain.blockchain.clone();
+        let recheck1 = recheck.clone();
+        let block_database = chain.block_db.clone();
+        let underpriced = chain.block_underpriced.clone();
+        let chain_name = name.clone();
+        handles.push(thread::spawn(move || {
+            submitter::run_block(
+                transactions.into_iter(),
+                listener,
+                block_database,
+                blockchain,
+                control,
+                recheck1,
+                underpriced,
+                submit_earlier,
+            ).map_err(|e| format!("Error starting submitters of chain {}: {:?}", chain_name, e))
+        }));
+
+        let control = chain.control.clone();
+        let blockchain = chain.blockchain.clone();
+        let timestamp_database = chain.timestamp_db.clone();
+        let underpriced = chain.timestamp_underpriced.clone();
+        let chain_name = name.clone();
+        handles.push(thread::spawn(move || {
+            submitter::run_timestamp(
+                transaction_nodes.into_iter(),
+                timestamp_database,
+                blockchain,
+                control,
+                recheck,
+                underpriced,
+            ).map_err(|e| format!("Error starting submitters of chain {}: {:?}", chain_name, e))
+        }));
+
+        // Updater is responsible for notifying about latest block (until shutdown).
+        let control = chain.control.clone();
+        handles.push(thread::spawn(move || {
+            updater.run(blockchain_nodes, control)
+                .map_err(|e| format!("Error starting blockchain updater of chain {}: {:?}", name, e))
+        }));
+    }
+
+    // SIGHUP reloads the config.
+    // On termination signal stop accepting requests first and then stop the submitters.
+    // Second termination signal terminates the process immediately.
+    thread::spawn(move || {
+        let mut server = Some(server);
+        for signal in signals.forever() {
+            if signal == signal_hook::SIGHUP {
+                if let Err(err) = reloader.reload() {
+                    error!("Config not reloaded: {}", err);
+                }
+                continue;
+            }
+
+            match server.take() {
+                Some(server) => {
+                    info!("Received signal {}. Shutting down.", signal);
+                    server.close();
+                    for control in &controls {
+                        control.shutdown();
+                    }
+                },
+                None => {
+                    warn!("Received signal {} again. Exiting without waiting for submissions.", signal);
+                    process::exit(2);
+                },
+            }
+        }
+    });
+
+    // Wait for in-flight submissions.
+    let errors = handles.into_iter()
+        .filter_map(|handle| match handle.join() {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err),
+            Err(_) => Some("Submitter, updater or garbage collector thread panicked.".into()),
+        })
+        .collect::<Vec<String>>();
+    for err in &errors {
+        error!("{}", err);
+    }
+
+    // Queued, forced and underpriced transactions are all kept in the databases.
+    let mut flushed = true;
+    for database in &databases {
+        if let Err(err) = database.flush() {
+            error!("Error flushing database: {:?}", err);
+            flushed = false;
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err("Submitters exited with errors.".into());
+    }
+    if !flushed {
+        return Err("Unable to flush databases.".into());
+    }
+
+    Ok("Shutdown complete.".into())
+}
+
+/// Refuses to serve multiple chains while the database of a single-chain setup holds transactions
+/// (they are stored directly in `rpc.db_path` and would never be submitted).
+fn check_single_chain_database(db_path: &str) -> Result<(), String> {
+    let dirs = Some("").into_iter()
+        .chain(config::SINGLE_CHAIN_DIRS.iter().cloned())
+        .chain(Some("time_ms/underpriced"));
+    for dir in dirs {
+        let path = path::Path::new(db_path).join(dir);
+        let has_transactions = database::Database::has_transactions(&path)
+            .map_err(|e| format!("Error reading database at {}: {:?}", path.display(), e))?;
+        if has_transactions {
+            return Err(format!(
+                "Database at {} holds transactions scheduled without `[[chains]]`. \
+                Move its files and subdirectories to {}/<name> of the chain they belong to before serving multiple chains.",
+                db_path, db_path.trim_right_matches('/'),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Components of a single chain started before the JSON-RPC server.
+struct ChainServices {
+    name: String,
+    chain: server::Chain,
+    blockchain_nodes: Vec<Endpoint>,
+    transaction_nodes: Vec<Endpoint>,
+    /// Configured submission nodes (compared on reload).
+    transactions: Vec<config::Node>,
+    updater: blockchain::Updater,
+    listener: mpsc::Receiver<BlockNumber>,
+    /// Removes acknowledged transactions until shutdown.
+    garbage_collector: thread::JoinHandle<Result<(), String>>,
+    recheck: Option<Arc<gas_price::GasPriceOracle>>,
+    submit_earlier: u64,
+}
+
+/// Verifies chain id of the nodes, starts blockchain cache and opens databases of a chain.
+fn start_chain(name: &str, config: &config::Config) -> Result<ChainServices, String> {
+    // Make sure all nodes are connected to the same chain.
+    let blockchain_nodes = config::endpoints(&config.nodes.blockchain.to_vec())?;
+    let transaction_nodes = config::endpoints(&config.nodes.transactions)?;
+    let all_nodes = blockchain_nodes.iter()
+        .chain(&transaction_nodes)
+        .cloned()
+        .collect::<Vec<_>>();
+    let chain_id = chain::Monitor::new(all_nodes.clone())
+        .and_then(|monitor| monitor.verify(config.verification.chain_id))
+        .map_err(|e| format!("Error verifying chain id: {:?}", e))?;
+
+    let options = Arc::new(SharedOptions::new(options(config, chain_id)?));
+
     // A cached state of blockchain.
-    let blockchain = Arc::new(blockchain::Blockchain::new(&blockchain_node_address, certifier)
+    let cache_options = cache_options(config.cache.as_ref());
+    let state_block = config.verification.state_block.unwrap_or_default();
+    let cross_check = config.nodes.cross_check.unwrap_or(false);
+    let blockchain = Arc::new(blockchain::Blockchain::new(&blockchain_nodes, cache_options.clone(), state_block, cross_check)
         .map_err(|e| format!("Error starting blockchain cache: {:?}", e))?
     );
 
+    // Sender admission policies (certifier is a shorthand for a certifier policy).
+    let mut policies = Vec::new();
+    if let Some(ref certifier) = config.verification.certifier {
+        let admission = config::Admission::Certifier { address: certifier.clone() };
+        policies.push(admission_policy(&admission, &blockchain, &cache_options)?);
+    }
+    if let Some(ref admission) = config.verification.admission {
+        policies.push(admission_policy(admission, &blockchain, &cache_options)?);
+    }
+    // Everyone is allowed if no policy is configured.
+    let policy = Arc::new(policy::All(policies));
+
+    // Minimal gas price (static or derived from the network).
+    let gas_price = Arc::new(gas_price::GasPriceOracle::new(blockchain.clone(), options.clone()));
+    let recheck = match config.verification.recheck_gas_price {
+        Some(true) => Some(gas_price.clone()),
+        _ => None,
+    };
+
     // TODO [ToDr] The limit is not shared between dbs.
     let max_txs_per_sender= config.verification.max_txs_per_sender;
     let block_database = Arc::new(database::Database::open(&config.rpc.db_path, max_txs_per_sender)
-        .map_err(|e| format!("Error opening database: {:?}", e))?);
-    let timestamp_database = Arc::new(database::Database::open(&format!("{}/time/", config.rpc.db_path), max_txs_per_sender)
-        .map_err(|e| format!("Error opening database: {:?}", e))?);
+        .map_err(|e| format!("Error opening database: {:?}", e))?
+        .with_options(options.clone()));
+
+    // Runtime control of submitters (used by admin methods).
+    let control = Arc::new(submitter::Control::default());
+
+    // Changes of timestamp database wake up the timestamp submitter.
+    let timestamp_path = format!("{}/time_ms/", config.rpc.db_path);
+    migrate_timestamps(&format!("{}/time/", config.rpc.db_path), &timestamp_path)
+        .map_err(|e| format!("Error migrating timestamp database: {:?}", e))?;
+    let timestamp_database = Arc::new(database::Database::open(&timestamp_path, max_txs_per_sender)
+        .map_err(|e| format!("Error opening database: {:?}", e))?
+        .with_options(options.clone())
+        .with_changes(control.changes()));
+
+    // Transactions skipped at submission time because of too low gas price (kept until requeued or removed).
+    let open_underpriced = |path: String| database::Database::open(&path, usize::max_value())
+        .map(Arc::new)
+        .map_err(|e| format!("Error opening underpriced database: {:?}", e));
+    let block_underpriced = open_underpriced(format!("{}/underpriced/", config.rpc.db_path))?;
+    let timestamp_underpriced = open_underpriced(format!("{}/time_ms/underpriced/", config.rpc.db_path))?;
+
+    // Periodically remove acknowledged transactions (until shutdown).
+    let retention = time::Duration::from_secs(config.rpc.db_retention_hours.unwrap_or(7 * 24) * 3600);
+    let garbage_collector = {
+        let databases = vec![block_database.clone(), timestamp_database.clone()];
+        let control = control.clone();
+        thread::spawn(move || {
+            while !control.is_shutting_down() {
+                for database in &databases {
+                    if let Err(err) = database.collect_garbage(retention) {
+                        warn!("Unable to remove acknowledged transactions: {:?}", err);
+                    }
+                }
+                for _ in 0..3600 {
+                    if control.is_shutting_down() {
+                        break;
+                    }
+                    thread::sleep(time::Duration::from_secs(1));
+                }
+            }
+            Ok(())
+        })
+    };
 
-    // Updater is responsible for notifying about latest block.
     let (updater, listener) = blockchain::Updater::new(
         blockchain.clone(),
     );
 
-    // A JSON-RPC server verifying and accepting requests.
-    let server = server::start(
-        block_database.clone(),
-        timestamp_database.clone(),
-        blockchain.clone(),
-        options,
-    )
-    .map_err(|e| e.to_string())?;
-
-    // spawn submitters
-    let transactions = config.nodes.transactions.clone();
-    let submit_earlier = config.rpc.submit_earlier;
-    let handle = thread::spawn(move || {
-        submitter::run_block(
-            transactions.into_iter().map(TransportType::Http),
-            listener,
-            block_database,
-            submit_earlier,
-        ).map_err(|e| error!("Error starting submitters: {:?}", e))
-    });
-    let _handle = thread::spawn(move || {
-        submitter::run_timestamp(
-            config.nodes.transactions.into_iter().map(TransportType::Http),
-            timestamp_database,
-        ).map_err(|e| error!("Error starting submitters: {:?}", e))
-    });
+    // Periodically re-verify the chain of all nodes.
+    let chain_check_interval = config.nodes.chain_check_interval_seconds.unwrap_or(60);
+    if chain_check_interval > 0 {
+        let control = control.clone();
+        thread::spawn(move || {
+            chain::Monitor::new(all_nodes)
+                .map(|monitor| monitor.run(chain_id, control, time::Duration::from_secs(chain_check_interval)))
+                .map_err(|e| error!("Error starting chain monitor: {:?}", e))
+        });
+    }
+
+    Ok(ChainServices {
+        name: name.to_owned(),
+        chain: server::Chain {
+            chain_id,
+            block_db: block_database,
+            timestamp_db: timestamp_database,
+            block_underpriced,
+            timestamp_underpriced,
+            blockchain,
+            control,
+            policy,
+            gas_price,
+            options,
+        },
+        blockchain_nodes,
+        transaction_nodes,
+        transactions: config.nodes.transactions.clone(),
+        updater,
+        listener,
+        garbage_collector,
+        recheck,
+        submit_earlier: config.rpc.submit_earlier,
+    })
+}
+
+/// Constructs verification and RPC options from the config.
+pub fn options(config: &config::Config, chain_id: u64) -> Result<Options, String> {
+    Ok(Options {
+        api_keys: api_keys(&config.rpc)?,
+        ..verification_options(config, chain_id)?
+    })
+}
+
+/// Constructs options from the config without loading the API keys (enough to verify transactions).
+pub fn verification_options(config: &config::Config, chain_id: u64) -> Result<Options, String> {
+    let ipc_permissions = match config.rpc.ipc_permissions {
+        None => 0o600,
+        Some(ref mode) => u32::from_str_radix(mode, 8)
+            .map_err(|e| format!("Invalid IPC permissions {}: {}", mode, e))?,
+    };
+
+    let rules = match config.verification.rules {
+        Some(ref rules) => rules::Rules::from_config(rules).map_err(|e| format!("Invalid verification rules: {}", e))?,
+        None => Default::default(),
+    };
+
+    let schedule = match config.verification.forks {
+        Some(ref forks) => schedule::Schedule::from_config(forks).map_err(|e| format!("Invalid fork schedule: {}", e))?,
+        None => schedule::Schedule::for_chain(chain_id),
+    };
+
+    Ok(Options {
+        chain_id,
+        schedule,
+        max_gas: config.verification.max_gas,
+        min_gas_price: config.verification.min_gas_price,
+        gas_price: config.verification.gas_price.unwrap_or_default(),
+        max_schedule_block: config.verification.max_schedule_block,
+        max_schedule_seconds: config.verification.max_schedule_seconds,
+        past_targets: config.verification.past_targets.unwrap_or_default(),
+        min_lead_blocks: config.verification.min_lead_blocks.unwrap_or(0),
+        min_lead_seconds: config.verification.min_lead_seconds.unwrap_or(0),
+        strict_nonce: config.verification.strict_nonce,
+        count_in_flight: config.verification.count_in_flight.unwrap_or(false),
+        max_txs_per_sender: config.verification.max_txs_per_sender,
+        rules,
+        rpc_listen_address: format!("{}:{}", config.rpc.interface, config.rpc.port).parse().map_err(|e| format!("Invalid interface or port: {}", e))?,
+        rpc_server_threads: config.rpc.server_threads,
+        processing_threads: config.rpc.processing_threads,
+        ipc_path: config.rpc.ipc_path.clone().map(Into::into),
+        ipc_permissions,
+        admin_token: config.rpc.admin_token.clone(),
+        api_keys: ApiKeys::default(),
+        ip_rate_limit: limit(config.rpc.ip_requests_per_minute, config.rpc.ip_burst),
+        sender_rate_limit: limit(config.rpc.sender_requests_per_minute, config.rpc.sender_burst),
+        trusted_proxies: match (config.rpc.trusted_proxies, config.rpc.trust_forwarded_for) {
+            (Some(proxies), _) => proxies,
+            // Single proxy in front of the server.
+            (None, Some(true)) => 1,
+            (None, _) => 0,
+        },
+    })
+}
+
+fn api_keys(rpc: &config::Rpc) -> Result<ApiKeys, String> {
+    let mut keys = HashMap::new();
+    if let Some(ref path) = rpc.api_keys_file {
+        let mut content = String::new();
+        fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("Unable to read API keys file at {}: {}", path, e))?;
+        let from_file: HashMap<String, config::ApiKey> = toml::from_str(&content)
+            .map_err(|e| format!("Invalid API keys file: {}", e))?;
+        keys.extend(from_file);
+    }
+    if let Some(ref inline) = rpc.api_keys {
+        keys.extend(inline.clone());
+    }
 
+    let keys = keys.into_iter().map(|(key, limits)| {
+        if key.is_empty() {
+            return Err("Invalid API key: the key can't be empty.".into());
+        }
+        Ok((key, ApiKey {
+            max_scheduled: limits.max_scheduled,
+            requests_per_minute: limits.requests_per_minute,
+            queues: limits.queues,
+        }))
+    }).collect::<Result<_, _>>()?;
+
+    Ok(ApiKeys {
+        required: rpc.api_keys_required.unwrap_or(false),
+        keys,
+    })
+}
+
+/// Copies transactions from the old timestamp database (with seconds precision) to the new one at `new_path`.
+///
+/// Transactions were already accepted, so the new database is opened without the per-sender limit.
+/// The migration can be safely interrupted: transactions already copied are skipped on the next run
+/// and the old database is only renamed (to `<path>.migrated`) after the new one is synced to disk.
+fn migrate_timestamps(path: &str, new_path: &str) -> database::Result<()> {
+    let migrated = format!("{}.migrated", path.trim_right_matches('/'));
+    if path::Path::new(path).exists() {
+        let old = database::Database::open(path, usize::max_value())?;
+        let database = database::Database::open(new_path, usize::max_value())?;
+        let existing = database.transactions()?.into_iter()
+            .map(|(_, transaction)| *transaction.hash())
+            .collect::<HashSet<_>>();
+        for (time, transaction) in old.transactions()? {
+            if existing.contains(transaction.hash()) {
+                continue;
+            }
+            match time.checked_mul(1000) {
+                Some(time) => database.insert(time, transaction)?,
+                None => warn!("[{:?}] Dropping transaction with timestamp out of range: {}", transaction.hash(), time),
+            }
+        }
+        database.flush()?;
+        // Release the lock of the old database before it's renamed.
+        drop(old);
+        // Completion marker: the old database is not read again.
+        fs::rename(path.trim_right_matches('/'), &migrated)?;
+        info!("Migrated timestamp database from {}", path);
+    }
+
+    if path::Path::new(&migrated).exists() {
+        fs::remove_dir_all(&migrated)?;
+    }
+    Ok(())
+}
 
-    // Blockchain updater uses the main thread.
-    updater.run(TransportType::Http(blockchain_node_address))
-        .map_err(|e| format!("Error Starting blockchain updater: {:?}", e))?;
+fn admission_policy(
+    admission: &config::Admission,
+    blockchain: &Arc<blockchain::Blockchain>,
+    cache: &blockchain::CacheOptions,
+) -> Result<Box<policy::Policy>, String> {
+    use config::Admission::*;
 
-    // wait for server to finish
-    server.wait();
-    let _ = handle.join();
+    // Results of contract queries are cached, lists are cheap to check.
+    let cached = |policy| Box::new(policy::Cached::new(policy, cache.capacity, cache.certification_ttl)) as Box<policy::Policy>;
+    let parse_address = |address: &String| address.trim_left_matches("0x").parse()
+        .map_err(|e| format!("Unable to parse contract address {}: {:?}", address, e));
+    let nested = |policies: &[config::Admission]| policies.iter()
+        .map(|admission| admission_policy(admission, blockchain, cache))
+        .collect::<Result<Vec<_>, _>>();
 
-    Ok("done".into())
+    Ok(match *admission {
+        Certifier { ref address } => cached(policy::ContractCall::certifier(blockchain.clone(), parse_address(address)?)),
+        Contract { ref address, ref function } => cached(policy::ContractCall::new(blockchain.clone(), parse_address(address)?, function.clone())?),
+        Token { ref address, ref min_balance } => {
+            let min_balance = match *min_balance {
+                Some(ref value) => parse_u256(value).map_err(|e| format!("Invalid min_balance {}: {}", value, e))?,
+                None => 1.into(),
+            };
+            Box::new(policy::Cached::new(
+                policy::TokenHolder::new(blockchain.clone(), parse_address(address)?, min_balance),
+                cache.capacity,
+                cache.certification_ttl,
+            ))
+        },
+        Lists { ref path } => Box::new(policy::Lists::new(path.as_str())?),
+        All { ref policies } => Box::new(policy::All(nested(policies)?)),
+        Any { ref policies } => Box::new(policy::Any(nested(policies)?)),
+    })
+}
+
+/// Parses decimal or `0x`-prefixed hex number.
+fn parse_u256(value: &str) -> Result<U256, String> {
+    if value.starts_with("0x") {
+        value[2..].parse().map_err(|e| format!("{:?}", e))
+    } else {
+        U256::from_dec_str(value).map_err(|e| format!("{:?}", e))
+    }
+}
+
+fn limit(per_minute: Option<u32>, burst: Option<u32>) -> Option<Limit> {
+    per_minute.map(|per_minute| Limit {
+        per_minute,
+        burst: burst.unwrap_or(per_minute),
+    })
+}
+
+fn cache_options(cache: Option<&config::Cache>) -> blockchain::CacheOptions {
+    let mut options = blockchain::CacheOptions::default();
+    if let Some(cache) = cache {
+        if let Some(capacity) = cache.capacity {
+            options.capacity = capacity;
+        }
+        if let Some(ttl) = cache.balance_ttl_seconds {
+            options.balance_ttl = time::Duration::from_secs(ttl);
+        }
+        if let Some(ttl) = cache.certification_ttl_seconds {
+            options.certification_ttl = time::Duration::from_secs(ttl);
+        }
+        if let Some(ttl) = cache.in_flight_ttl_seconds {
+            options.in_flight_ttl = time::Duration::from_secs(ttl);
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use transaction_scheduler::database::Database;
+    use transaction_scheduler::sign::{Signer, TransactionRequest};
+    use super::migrate_timestamps;
+
+    #[test]
+    fn should_migrate_transactions_above_sender_limit() {
+        let dir = TempDir::new("migration").unwrap();
+        let old_path = format!("{}/time/", dir.path().display());
+        let new_path = format!("{}/time_ms/", dir.path().display());
+        let signer = Signer::from_secret("0x4646464646464646464646464646464646464646464646464646464646464646").unwrap();
+        {
+            let old = Database::open(&old_path, usize::max_value()).unwrap();
+            for nonce in 0..3 {
+                old.insert(1_000 + nonce, signer.sign(TransactionRequest {
+                    nonce: nonce.into(),
+                    gas: 21_000.into(),
+                    ..Default::default()
+                }, 1)).unwrap();
+            }
+            old.flush().unwrap();
+        }
+
+        migrate_timestamps(&old_path, &new_path).unwrap();
+
+        // The current limit is lower than the number of migrated transactions.
+        let new = Database::open(&new_path, 1).unwrap();
+        let times = new.transactions().unwrap().into_iter().map(|(time, _)| time).collect::<Vec<_>>();
+        assert_eq!(times, vec![1_000_000, 1_001_000, 1_002_000]);
+        assert!(!new.sender_allowed(&signer.address()));
+        assert!(!::std::path::Path::new(&old_path).exists());
+    }
 }
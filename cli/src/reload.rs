@@ -0,0 +1,172 @@
+//! Configuration reload (on `SIGHUP` or `admin_reloadConfig`).
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use transaction_scheduler::submitter::{self, Control};
+use transaction_scheduler::SharedOptions;
+
+use config::{self, Config, Loader};
+use options;
+
+/// Reloadable components of a single chain.
+pub struct Target {
+    /// Chain name (see `Config::chains`).
+    pub name: String,
+    pub chain_id: u64,
+    /// Submission nodes the chain was started with.
+    pub transactions: Vec<config::Node>,
+    /// Verification options (including sender quota and gas price policy).
+    pub options: Arc<SharedOptions>,
+    pub control: Arc<Control>,
+}
+
+/// Applies verification options, sender quota, gas price policy
+/// and the list of submission nodes of every chain from re-read configuration.
+pub struct Reloader {
+    loader: Loader,
+    /// Config the scheduler was started with.
+    initial: Config,
+    /// Submission nodes currently in use (per target).
+    transactions: Mutex<Vec<Vec<config::Node>>>,
+    targets: Vec<Target>,
+}
+
+impl Reloader {
+    /// Creates new reloader of configuration (loaded initially as `config`).
+    ///
+    /// `targets` have to be given in the order of `config.chains()`.
+    pub fn new(loader: Loader, config: Config, targets: Vec<Target>) -> Self {
+        Reloader {
+            loader,
+            initial: config,
+            transactions: Mutex::new(targets.iter().map(|target| target.transactions.clone()).collect()),
+            targets,
+        }
+    }
+
+    /// Re-reads (including config file and environment) and validates the config and applies the changes.
+    ///
+    /// Nothing is applied if the config is invalid, the list of chains has changed
+    /// or new submission nodes are not reachable.
+    /// Returns names of changed settings that require restart.
+    pub fn reload(&self) -> Result<Vec<String>, String> {
+        info!("Reloading config.");
+        let config = self.loader.load()?;
+        let initial = self.initial.chains()?;
+        let chains = config.chains()?;
+        let is_same = chains.len() == self.targets.len() && chains.iter().zip(&self.targets)
+            .all(|(&(ref name, _), target)| *name == target.name);
+        if !is_same {
+            return Err("Chains were added, removed or renamed, restart is required.".into());
+        }
+
+        // Reloads are serialized by the lock.
+        let mut current = self.transactions.lock();
+
+        // Validate all chains (and connect to changed submission nodes) before applying anything.
+        let updates = chains.iter().zip(&self.targets).zip(current.iter()).map(|((&(_, ref config), target), transactions)| {
+            let options = options(config, target.chain_id)?;
+            let transaction_nodes = if *transactions != config.nodes.transactions {
+                let nodes = config::endpoints(&config.nodes.transactions)?;
+                submitter::verify_transports(&nodes)
+                    .map_err(|e| format!("Unable to connect to submission nodes of chain {}: {:?}", target.name, e))?;
+                Some(nodes)
+            } else {
+                None
+            };
+            Ok((config, options, transaction_nodes))
+        }).collect::<Result<Vec<_>, String>>()?;
+
+        for ((target, (config, options, transaction_nodes)), transactions) in self.targets.iter().zip(updates).zip(current.iter_mut()) {
+            // Sender quota and gas price policy are read from the options, so they change at once.
+            target.options.set(options);
+            if let Some(nodes) = transaction_nodes {
+                target.control.set_transports(nodes);
+                *transactions = config.nodes.transactions.clone();
+            }
+        }
+
+        let mut settings = Vec::new();
+        for (&(ref name, ref old), &(_, ref new)) in initial.iter().zip(&chains) {
+            for setting in restart_required(old, new) {
+                // Chain settings are configured in `[[chains]]` sections.
+                let setting = match self.initial.chains {
+                    Some(_) if !setting.starts_with("rpc.") => format!("chains.{}.{}", name, setting),
+                    _ => setting,
+                };
+                if !settings.contains(&setting) {
+                    settings.push(setting);
+                }
+            }
+        }
+        for setting in &settings {
+            warn!("Changed `{}` requires restart to take effect.", setting);
+        }
+        info!("Config reloaded.");
+        Ok(settings)
+    }
+}
+
+/// Returns names of settings which are applied only at startup and differ between the configs.
+fn restart_required(old: &Config, new: &Config) -> Vec<String> {
+    let mut changed = Vec::new();
+    {
+        let mut check = |name: &str, is_changed: bool| if is_changed {
+            changed.push(name.to_owned());
+        };
+        let (a, b) = (&old.rpc, &new.rpc);
+        check("rpc.interface", a.interface != b.interface);
+        check("rpc.port", a.port != b.port);
+        check("rpc.server_threads", a.server_threads != b.server_threads);
+        check("rpc.processing_threads", a.processing_threads != b.processing_threads);
+        check("rpc.db_path", a.db_path != b.db_path);
+        check("rpc.db_retention_hours", a.db_retention_hours != b.db_retention_hours);
+        check("rpc.submit_earlier", a.submit_earlier != b.submit_earlier);
+        check("rpc.ipc_path", a.ipc_path != b.ipc_path);
+        check("rpc.ipc_permissions", a.ipc_permissions != b.ipc_permissions);
+        check("rpc.admin_token", a.admin_token != b.admin_token);
+        check("rpc.api_keys_required", a.api_keys_required != b.api_keys_required);
+        check("rpc.api_keys_file", a.api_keys_file != b.api_keys_file);
+        check("rpc.api_keys", a.api_keys != b.api_keys);
+        check("rpc.ip_requests_per_minute", a.ip_requests_per_minute != b.ip_requests_per_minute);
+        check("rpc.ip_burst", a.ip_burst != b.ip_burst);
+        check("rpc.sender_requests_per_minute", a.sender_requests_per_minute != b.sender_requests_per_minute);
+        check("rpc.sender_burst", a.sender_burst != b.sender_burst);
+        check("rpc.trust_forwarded_for", a.trust_forwarded_for != b.trust_forwarded_for);
+        check("rpc.trusted_proxies", a.trusted_proxies != b.trusted_proxies);
+        check("verification.chain_id", old.verification.chain_id != new.verification.chain_id);
+        check("verification.recheck_gas_price", old.verification.recheck_gas_price != new.verification.recheck_gas_price);
+        check("verification.state_block", old.verification.state_block != new.verification.state_block);
+        check("verification.certifier", old.verification.certifier != new.verification.certifier);
+        check("verification.admission", old.verification.admission != new.verification.admission);
+        check("nodes.blockchain", old.nodes.blockchain != new.nodes.blockchain);
+        check("nodes.cross_check", old.nodes.cross_check != new.nodes.cross_check);
+        check("nodes.chain_check_interval_seconds", old.nodes.chain_check_interval_seconds != new.nodes.chain_check_interval_seconds);
+        check("cache", old.cache != new.cache);
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+    use config::{self, Config};
+    use super::restart_required;
+
+    #[test]
+    fn should_report_settings_requiring_restart() {
+        let old: Config = toml::from_str(include_str!("../../config.toml")).unwrap();
+        let mut new: Config = toml::from_str(include_str!("../../config.toml")).unwrap();
+        assert!(restart_required(&old, &new).is_empty());
+
+        new.verification.max_gas += 1;
+        new.verification.min_gas_price += 1;
+        new.nodes.transactions.push(config::Node::Url("http://127.0.0.1:8546".into()));
+        assert!(restart_required(&old, &new).is_empty());
+
+        new.rpc.port += 1;
+        new.verification.chain_id = Some(1);
+        assert_eq!(restart_required(&old, &new), vec!["rpc.port".to_owned(), "verification.chain_id".to_owned()]);
+    }
+}
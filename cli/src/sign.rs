@@ -0,0 +1,175 @@
+//! Offline transaction signing (`txsched sign`).
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::Future;
+use rustc_hex::{FromHex, ToHex};
+use transaction_scheduler::blockchain::{Blockchain, CacheOptions};
+use transaction_scheduler::chain;
+use transaction_scheduler::sign::{Signer, TransactionRequest};
+use transaction_scheduler::types::U256;
+use transaction_scheduler::verify_transaction;
+
+use config::{self, Config};
+use {verification_options, Args};
+
+/// Builds, signs and verifies the transaction. Returns hex-encoded RLP.
+pub fn execute(args: &Args, config: &Config) -> Result<String, String> {
+    let signer = signer(args)?;
+    let mut request = TransactionRequest {
+        to: match args.flag_to {
+            Some(ref to) => Some(to.trim_left_matches("0x").parse().map_err(|e| format!("Invalid recipient {}: {:?}", to, e))?),
+            None => None,
+        },
+        value: number("value", &args.flag_value)?,
+        data: args.flag_data.trim_left_matches("0x").from_hex().map_err(|e| format!("Invalid data: {}", e))?,
+        ..Default::default()
+    };
+
+    let blockchain_nodes = config::endpoints(&config.nodes.blockchain.to_vec())?;
+    let chain_id = match config.verification.chain_id {
+        Some(chain_id) => chain_id,
+        None => chain::Monitor::new(blockchain_nodes.clone())
+            .and_then(|monitor| monitor.verify(None))
+            .map_err(|e| format!("Error detecting chain id: {:?}", e))?,
+    };
+    let options = verification_options(config, chain_id)?;
+
+    // Intrinsic gas is checked against the fork active at submission time (latest known fork by default).
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| format!("Invalid system time: {}", e))?.as_secs();
+    let gas_schedule = options.schedule.at(args.flag_block.unwrap_or(u64::max_value()), args.flag_time.unwrap_or(now));
+    let intrinsic_gas: U256 = gas_schedule.intrinsic_gas(&request.action(), &request.data).into();
+
+    // Missing gas (of transactions with data), nonce and gas price are fetched from the blockchain nodes.
+    let estimate_gas = args.flag_gas.is_none() && !request.data.is_empty();
+    let blockchain = if estimate_gas || args.flag_nonce.is_none() || args.flag_gas_price.is_none() {
+        Some(Blockchain::new(
+            &blockchain_nodes,
+            CacheOptions::default(),
+            config.verification.state_block.unwrap_or_default(),
+            false,
+        ).map_err(|e| format!("Error connecting to blockchain nodes: {:?}", e))?)
+    } else {
+        None
+    };
+
+    request.gas = match args.flag_gas {
+        Some(ref gas) => number("gas", gas)?,
+        // Calls and contract creations need more than intrinsic gas.
+        None if estimate_gas => {
+            let blockchain = blockchain.as_ref().expect("Connected if gas is estimated; qed");
+            let gas = blockchain.estimate_gas(signer.address(), request.to, request.value, request.data.clone()).wait()
+                .map_err(|e| format!("Error estimating gas (pass --gas instead): {:?}", e))?;
+            ::std::cmp::max(gas, intrinsic_gas)
+        },
+        None => intrinsic_gas,
+    };
+
+    let (nonce, gas_price) = match (args.flag_nonce.as_ref(), args.flag_gas_price.as_ref()) {
+        (Some(nonce), Some(gas_price)) => (number("nonce", nonce)?, number("gas price", gas_price)?),
+        (nonce, gas_price) => {
+            let blockchain = blockchain.as_ref().expect("Connected if nonce or gas price is missing; qed");
+
+            let nonce = match nonce {
+                Some(nonce) => number("nonce", nonce)?,
+                None => {
+                    let (balance, nonce) = blockchain.balance_and_nonce(signer.address()).wait()
+                        .map_err(|e| format!("Error fetching nonce: {:?}", e))?;
+                    info!("Sender {:?} balance: {}, nonce: {}", signer.address(), balance, nonce);
+                    nonce
+                },
+            };
+            let gas_price = match gas_price {
+                Some(gas_price) => number("gas price", gas_price)?,
+                None => {
+                    let gas_price = blockchain.gas_price().wait()
+                        .map_err(|e| format!("Error fetching gas price: {:?}", e))?;
+                    ::std::cmp::max(gas_price, options.min_gas_price.into())
+                },
+            };
+            (nonce, gas_price)
+        },
+    };
+    request.nonce = nonce;
+    request.gas_price = gas_price;
+
+    let transaction = signer.sign(request, chain_id);
+    verify_transaction(transaction.rlp().to_vec().into(), &options, &[gas_schedule])
+        .map_err(|e| format!("Transaction would be rejected by the scheduler: {}", e.message))?;
+
+    info!("Signed transaction {:?} from {:?} (chain id: {}, nonce: {}, gas price: {})",
+        transaction.hash(), transaction.sender(), chain_id, nonce, gas_price);
+    Ok(format!("0x{}", transaction.rlp().to_hex()))
+}
+
+fn signer(args: &Args) -> Result<Signer, String> {
+    match (args.flag_key.as_ref(), args.flag_keystore.as_ref()) {
+        (Some(key), None) => {
+            // Prefer reading the key from a file, so it doesn't end up in shell history.
+            if Path::new(key).is_file() {
+                Signer::from_secret(&read(key)?)
+            } else {
+                Signer::from_secret(key)
+            }
+        },
+        (None, Some(keystore)) => {
+            let password = match args.flag_password {
+                Some(ref path) => read(path)?.trim_right_matches(|c| c == '\n' || c == '\r').to_owned(),
+                None => String::new(),
+            };
+            Signer::from_keystore(keystore, &password)
+        },
+        _ => Err("Exactly one of --key or --keystore has to be specified.".into()),
+    }
+}
+
+fn read(path: &str) -> Result<String, String> {
+    let mut content = String::new();
+    fs::File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    Ok(content)
+}
+
+/// Parses decimal or `0x`-prefixed hex number.
+fn number(name: &str, value: &str) -> Result<U256, String> {
+    let invalid = || format!("Invalid {}: {}", name, value);
+    if value.starts_with("0x") {
+        return value[2..].parse().map_err(|_| invalid());
+    }
+    if value.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut result = U256::zero();
+    for c in value.chars() {
+        let digit = c.to_digit(10).ok_or_else(&invalid)?;
+        let (mul, overflow1) = result.overflowing_mul(10.into());
+        let (add, overflow2) = mul.overflowing_add(u64::from(digit).into());
+        if overflow1 || overflow2 {
+            return Err(invalid());
+        }
+        result = add;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use transaction_scheduler::types::U256;
+    use super::number;
+
+    #[test]
+    fn should_parse_numbers() {
+        assert_eq!(number("value", "0"), Ok(U256::zero()));
+        assert_eq!(number("value", "21000"), Ok(21_000.into()));
+        assert_eq!(number("value", "0x5208"), Ok(21_000.into()));
+        assert_eq!(number("value", "1000000000000000000000"), Ok(U256::from(1_000_000_000_000_000_000u64) * U256::from(1_000)));
+        assert!(number("value", "").is_err());
+        assert!(number("value", "1e18").is_err());
+        assert!(number("value", &format!("1{}", "0".repeat(78))).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Privileged JSON-RPC methods for operators.
+//!
+//! Methods are exposed under the `admin_` prefix and are only
+//! available to privileged requests (IPC or HTTP with a valid admin token).
+//!
+//! Transactions are found on the chain of their id. `admin_stats`, `admin_pause`, `admin_resume`
+//! and `admin_purgeSender` accept an optional chain id (the last parameter), which is required
+//! if more than one chain is served.
+//!
+//! Underpriced transactions are kept on disk until they are requeued or removed
+//! (`admin_takeUnderpriced` only lists them).
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use jsonrpc_core::{Error, MetaIoHandler, Params, Value};
+use serde_json;
+
+use database::{Database, Stats};
+use errors;
+use server::{Chain, Meta, Reload};
+use submitter::{self, Queue};
+use types::{Address, Bytes, Condition, TransactionId};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueueStats {
+    chain_id: u64,
+    latest_block: u64,
+    block_paused: bool,
+    timestamp_paused: bool,
+    halted: bool,
+    underpriced: usize,
+    block: Stats,
+    timestamp: Stats,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Reloaded {
+    restart_required: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnderpricedTransaction {
+    chain_id: u64,
+    id: Bytes,
+    hash: String,
+    sender: String,
+    rlp: Bytes,
+}
+
+/// Operator methods.
+#[derive(Clone)]
+pub struct Admin {
+    /// Chain of ids issued without chain id.
+    default: u64,
+    chains: Arc<BTreeMap<u64, Chain>>,
+    reload: Option<Arc<Reload>>,
+}
+
+impl Admin {
+    /// Creates new admin methods handler (the first chain is the default one).
+    ///
+    /// Panics if `chains` is empty.
+    pub fn new(
+        chains: Vec<Chain>,
+        reload: Option<Arc<Reload>>,
+    ) -> Self {
+        assert!(!chains.is_empty(), "Admin methods require at least one chain.");
+        Admin {
+            default: chains[0].chain_id,
+            chains: Arc::new(chains.into_iter().map(|chain| (chain.chain_id, chain)).collect()),
+            reload,
+        }
+    }
+
+    /// Adds `admin_*` methods to given handler.
+    pub fn extend(&self, io: &mut MetaIoHandler<Meta>) {
+        self.add(io, "admin_stats", |admin, params| {
+            let (params, chain_id) = chain_id_param(params, 0)?;
+            params.expect_no_params()?;
+            admin.stats(admin.selected(chain_id)?)
+        });
+        self.add(io, "admin_pause", |admin, params| {
+            let (queues, chain_id) = queues(params)?;
+            for chain in admin.chains(chain_id)? {
+                for queue in &queues {
+                    chain.control.set_paused(*queue, true);
+                }
+            }
+            Ok(Value::Bool(true))
+        });
+        self.add(io, "admin_resume", |admin, params| {
+            let (queues, chain_id) = queues(params)?;
+            for chain in admin.chains(chain_id)? {
+                for queue in &queues {
+                    chain.control.set_paused(*queue, false);
+                }
+            }
+            Ok(Value::Bool(true))
+        });
+        self.add(io, "admin_purgeSender", |admin, params| {
+            let (params, chain_id) = chain_id_param(params, 1)?;
+            let (sender, ) = params.parse::<(Bytes, )>()?;
+            admin.purge_sender(parse_address(sender)?, chain_id)
+        });
+        self.add(io, "admin_forceSubmit", |admin, params| {
+            let (id, ) = params.parse::<(Bytes, )>()?;
+            admin.force_submit(parse_id(id)?)
+        });
+        self.add(io, "admin_takeUnderpriced", |admin, params| {
+            params.expect_no_params()?;
+            admin.take_underpriced()
+        });
+        self.add(io, "admin_requeueUnderpriced", |admin, params| {
+            let (id, ) = params.parse::<(Bytes, )>()?;
+            admin.requeue_underpriced(parse_id(id)?)
+        });
+        self.add(io, "admin_removeUnderpriced", |admin, params| {
+            let (id, ) = params.parse::<(Bytes, )>()?;
+            admin.remove_underpriced(parse_id(id)?)
+        });
+        self.add(io, "admin_move", |admin, params| {
+            let (id, condition) = params.parse::<(Bytes, Condition)>()?;
+            admin.move_to(parse_id(id)?, condition)
+        });
+        self.add(io, "admin_reloadConfig", |admin, params| {
+            params.expect_no_params()?;
+            admin.reload()
+        });
+    }
+
+    fn add<F>(&self, io: &mut MetaIoHandler<Meta>, name: &'static str, method: F) where
+        F: Fn(&Admin, Params) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        let admin = self.clone();
+        io.add_method_with_meta(name, move |params: Params, meta: Meta| {
+            trace!("Incoming {} request: {:?}", name, params);
+            if !meta.admin {
+                warn!("Rejecting unauthorized {} request.", name);
+                return Err(errors::unauthorized());
+            }
+            method(&admin, params)
+        });
+    }
+
+    /// Returns chain of a transaction id (ids issued without chain id belong to the default chain).
+    fn chain(&self, chain_id: Option<u64>) -> Result<&Chain, Error> {
+        let chain_id = chain_id.unwrap_or(self.default);
+        self.chains.get(&chain_id).ok_or_else(|| errors::unsupported_chain(chain_id))
+    }
+
+    /// Returns chain selected by the operator (chain id is required if more than one chain is served).
+    fn selected(&self, chain_id: Option<u64>) -> Result<&Chain, Error> {
+        match chain_id {
+            Some(chain_id) => self.chain(Some(chain_id)),
+            None if self.chains.len() == 1 => self.chain(None),
+            None => Err(errors::chain_not_selected()),
+        }
+    }
+
+    /// Returns chain selected by the operator as a list (see `selected`).
+    fn chains(&self, chain_id: Option<u64>) -> Result<Vec<&Chain>, Error> {
+        self.selected(chain_id).map(|chain| vec![chain])
+    }
+
+    fn stats(&self, chain: &Chain) -> Result<Value, Error> {
+        serde_json::to_value(&QueueStats {
+            chain_id: chain.chain_id,
+            latest_block: chain.blockchain.latest_block(),
+            block_paused: chain.control.is_paused(Queue::Block),
+            timestamp_paused: chain.control.is_paused(Queue::Timestamp),
+            halted: chain.control.is_halted(),
+            underpriced: chain.block_underpriced.stats().transactions + chain.timestamp_underpriced.stats().transactions,
+            block: chain.block_db.stats(),
+            timestamp: chain.timestamp_db.stats(),
+        }).map_err(errors::internal)
+    }
+
+    fn purge_sender(&self, sender: Address, chain_id: Option<u64>) -> Result<Value, Error> {
+        let mut removed = Vec::new();
+        for chain in self.chains(chain_id)? {
+            removed.extend(chain.block_db.remove_sender(&sender).map_err(errors::internal)?);
+            removed.extend(chain.timestamp_db.remove_sender(&sender).map_err(errors::internal)?);
+        }
+        info!("Purged {} transactions of {:?}", removed.len(), sender);
+
+        let hashes = removed.iter().map(|tx| Value::String(format!("{:?}", tx.hash()))).collect();
+        Ok(Value::Array(hashes))
+    }
+
+    fn reload(&self) -> Result<Value, Error> {
+        let reload = self.reload.as_ref().ok_or_else(|| errors::internal("Config reload is not supported."))?;
+        let restart_required = reload().map_err(errors::internal)?;
+        serde_json::to_value(&Reloaded { restart_required }).map_err(errors::internal)
+    }
+
+    /// Lists underpriced transactions of all chains (the transactions are not removed).
+    fn take_underpriced(&self) -> Result<Value, Error> {
+        let mut transactions = Vec::new();
+        for chain in self.chains.values() {
+            for &is_timestamp in &[false, true] {
+                let underpriced = underpriced(chain, is_timestamp).transactions().map_err(errors::internal)?;
+                transactions.extend(underpriced.into_iter().map(|(num, tx)| UnderpricedTransaction {
+                    chain_id: chain.chain_id,
+                    id: TransactionId {
+                        is_timestamp,
+                        immediate: false,
+                        num,
+                        hash: *tx.hash(),
+                        chain_id: Some(chain.chain_id),
+                    }.to_bytes(),
+                    hash: format!("{:?}", tx.hash()),
+                    sender: format!("{:?}", tx.sender()),
+                    rlp: tx.rlp().to_vec().into(),
+                }));
+            }
+        }
+        serde_json::to_value(&transactions).map_err(errors::internal)
+    }
+
+    /// Moves underpriced transaction back to its original queue and target.
+    fn requeue_underpriced(&self, id: TransactionId) -> Result<Value, Error> {
+        let chain = self.chain(id.chain_id)?;
+        transfer(underpriced(chain, id.is_timestamp), database(chain, id.is_timestamp), &id, id.num)?;
+        info!("[{:?}] Requeued underpriced transaction.", id.hash);
+        Ok(Value::Bool(true))
+    }
+
+    /// Removes underpriced transaction permanently.
+    fn remove_underpriced(&self, id: TransactionId) -> Result<Value, Error> {
+        let chain = self.chain(id.chain_id)?;
+        underpriced(chain, id.is_timestamp).remove(&id.num, &id.hash)
+            .map_err(errors::internal)?
+            .ok_or_else(|| errors::not_found())?;
+        info!("[{:?}] Removed underpriced transaction.", id.hash);
+        Ok(Value::Bool(true))
+    }
+
+    /// Moves the transaction to the forced target of the timestamp queue,
+    /// it's submitted (and acknowledged) like any other scheduled transaction.
+    fn force_submit(&self, id: TransactionId) -> Result<Value, Error> {
+        let chain = self.chain(id.chain_id)?;
+        info!("[{:?}] Forcing submission.", id.hash);
+        move_transaction(chain, &id, true, submitter::FORCED)?;
+        Ok(Value::Bool(true))
+    }
+
+    fn move_to(&self, id: TransactionId, condition: Condition) -> Result<Value, Error> {
+        let (is_timestamp, num) = condition.target().ok_or_else(errors::invalid_condition)?;
+
+        let chain = self.chain(id.chain_id)?;
+        move_transaction(chain, &id, is_timestamp, num)?;
+
+        info!("[{:?}] Moved from {} to {}", id.hash, id.num, num);
+        Ok(serde_json::to_value(&TransactionId {
+            is_timestamp,
+            immediate: false,
+            num,
+            hash: id.hash,
+            chain_id: Some(chain.chain_id),
+        }.to_bytes()).expect("Bytes serialization is infallible."))
+    }
+}
+
+fn database(chain: &Chain, is_timestamp: bool) -> &Database {
+    if is_timestamp { &chain.timestamp_db } else { &chain.block_db }
+}
+
+fn underpriced(chain: &Chain, is_timestamp: bool) -> &Database {
+    if is_timestamp { &chain.timestamp_underpriced } else { &chain.block_underpriced }
+}
+
+/// Moves transaction with given id to another queue or target.
+fn move_transaction(chain: &Chain, id: &TransactionId, is_timestamp: bool, num: u64) -> Result<(), Error> {
+    transfer(database(chain, id.is_timestamp), database(chain, is_timestamp), id, num)
+}
+
+/// Moves transaction with given id from `source` to `num` of `destination`.
+fn transfer(source: &Database, destination: &Database, id: &TransactionId, num: u64) -> Result<(), Error> {
+    let transaction = source.remove(&id.num, &id.hash)
+        .map_err(errors::internal)?
+        .ok_or_else(|| errors::not_found())?;
+
+    if let Err(err) = destination.insert(num, transaction.clone()) {
+        warn!("[{:?}] Unable to move transaction: {:?}", id.hash, err);
+        // Put the transaction back to where it was.
+        if let Err(err) = source.insert(id.num, transaction) {
+            error!("[{:?}] Unable to restore transaction: {:?}", id.hash, err);
+        }
+        return Err(errors::internal(err));
+    }
+    Ok(())
+}
+
+/// Parses optional queue and chain id (all queues by default).
+fn queues(params: Params) -> Result<(Vec<Queue>, Option<u64>), Error> {
+    match params {
+        Params::None => Ok((vec![Queue::Block, Queue::Timestamp], None)),
+        Params::Array(ref values) if values.is_empty() => Ok((vec![Queue::Block, Queue::Timestamp], None)),
+        params => {
+            let (params, chain_id) = chain_id_param(params, 1)?;
+            let (queue, ) = params.parse::<(Queue, )>()?;
+            Ok((vec![queue], chain_id))
+        },
+    }
+}
+
+/// Splits the optional chain id, given after `count` other parameters, from the parameters.
+///
+/// Chain id has to be a number, anything else is reported as an invalid chain id
+/// (instead of a mismatch of the other parameters).
+pub fn chain_id_param(params: Params, count: usize) -> Result<(Params, Option<u64>), Error> {
+    let mut values = match params {
+        Params::None => vec![],
+        Params::Array(values) => values,
+        Params::Map(_) => return Err(Error::invalid_params("Expected an array of parameters.")),
+    };
+    if values.len() != count + 1 {
+        return Ok((Params::Array(values), None));
+    }
+
+    let chain_id = values.pop().expect("There are count + 1 values; qed");
+    let chain_id = serde_json::from_value::<u64>(chain_id).map_err(|_| errors::invalid_chain_id())?;
+    Ok((Params::Array(values), Some(chain_id)))
+}
+
+fn parse_address(address: Bytes) -> Result<Address, Error> {
+    let address = address.into_vec();
+    if address.len() != 20 {
+        return Err(errors::invalid_address());
+    }
+    Ok(Address::from_slice(&address))
+}
+
+fn parse_id(id: Bytes) -> Result<TransactionId, Error> {
+    TransactionId::from_bytes(id).ok_or_else(|| errors::invalid_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::Params;
+    use super::chain_id_param;
+
+    fn params(json: &str) -> Params {
+        ::serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn should_split_optional_chain_id() {
+        assert_eq!(chain_id_param(params(r#"["0x01"]"#), 1).unwrap(), (params(r#"["0x01"]"#), None));
+        assert_eq!(chain_id_param(params(r#"["0x01", 42]"#), 1).unwrap(), (params(r#"["0x01"]"#), Some(42)));
+        assert_eq!(chain_id_param(params("[42]"), 0).unwrap(), (params("[]"), Some(42)));
+        assert!(chain_id_param(params(r#"["0x01", "0x2a"]"#), 1).is_err());
+    }
+}
@@ -1,71 +1,183 @@
 //! Blockchain state
 
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::{fmt, thread, time};
 
+use ethabi;
+use ethcore::transaction::UnverifiedTransaction;
 use futures::{sink, future, Sink, Future};
 use futures::sync::mpsc;
 use parking_lot::RwLock;
+use rlp::UntrustedRlp;
+use rustc_hex::ToHex;
+use tokio_timer::Timer;
 use web3::{self, Web3, Transport, contract, transports};
 use web3::api::{Eth, Namespace};
-use web3::transports::http::Http;
 
-use types::{Address, BlockNumber, U256};
-use TransportType;
+use cache::Cache;
+use submitter::Control;
+use transport::{self, Endpoint, NodeTransport};
+use types::{Address, BlockNumber, Transaction, U256};
 
 type BN = (U256, U256);
+
+/// Time (in milliseconds) after which a node not responding with the latest block is considered missing.
+const HEAD_TIMEOUT_MS: u64 = 2_000;
+
+/// Blockchain state caching options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheOptions {
+    /// Max number of cached entries (per cache).
+    pub capacity: usize,
+    /// Time after which cached balance and nonce expire.
+    pub balance_ttl: time::Duration,
+    /// Time after which cached admission of a sender expires (rejections are not cached).
+    pub certification_ttl: time::Duration,
+    /// Time after which submitted transactions are no longer considered in-flight.
+    pub in_flight_ttl: time::Duration,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            capacity: 10_000,
+            balance_ttl: time::Duration::from_secs(15),
+            certification_ttl: time::Duration::from_secs(3600),
+            in_flight_ttl: time::Duration::from_secs(600),
+        }
+    }
+}
+
+/// Block at which the state (balance & nonce) is queried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum StateBlock {
+    /// State of the latest mined block.
+    #[serde(rename="latest")]
+    Latest,
+    /// State including transactions in node's transaction pool.
+    #[serde(rename="pending")]
+    Pending,
+}
+
+impl Default for StateBlock {
+    fn default() -> Self {
+        StateBlock::Latest
+    }
+}
+
+impl From<StateBlock> for web3::types::BlockNumber {
+    fn from(block: StateBlock) -> Self {
+        match block {
+            StateBlock::Latest => web3::types::BlockNumber::Latest,
+            StateBlock::Pending => web3::types::BlockNumber::Pending,
+        }
+    }
+}
+
+type Query<R, E> = Box<Future<Item=R, Error=E> + Send>;
+
+/// A single node queried for blockchain state.
+struct Node<T: Transport> {
+    url: String,
+    transport: T,
+    web3: Web3<T>,
+}
+
 /// A structure responsible for maintaining and caching latest blockchain state, like:
 /// - latest block number
 /// - nonce for particular sender
-pub struct Blockchain<T: Transport = Arc<Http>> {
-    web3: Web3<T>,
-    _eloop: transports::EventLoopHandle,
+///
+/// State queries are sent to the first node and fail over to subsequent nodes on error.
+/// In `cross_check` mode all nodes are queried and the first two successful results are compared instead.
+pub struct Blockchain<T: Transport = NodeTransport> {
+    nodes: Arc<Vec<Node<T>>>,
+    _eloops: Vec<transports::EventLoopHandle>,
+    cross_check: bool,
     latest_block: RwLock<BlockNumber>,
-    // TODO [ToDr] Caching can lead to OOM. Might be worth to introduce some eviction.
-    cached_balance_and_nonce: Arc<RwLock<HashMap<Address, BN>>>,
-    cached_certification: Arc<RwLock<HashMap<Address, bool>>>,
-    certifier: Option<contract::Contract<T>>,
+    cached_balance_and_nonce: Arc<Cache<Address, BN>>,
+    // Nonces of transactions submitted by the scheduler (possibly not yet mined).
+    in_flight: Cache<Address, BTreeSet<U256>>,
+    state_block: StateBlock,
 }
 
 impl<T: Transport> fmt::Debug for Blockchain<T> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Blockchain")
+            .field("nodes", &self.nodes.iter().map(|node| &node.url).collect::<Vec<_>>())
             .field("latest_block", &self.latest_block)
-            .field("cached", &self.cached_balance_and_nonce)
             .finish()
     }
 }
 
 impl Blockchain {
     /// Create a new cached blockchain client.
-    pub fn new(url: &str, certifier: Option<Address>) -> Result<Self, web3::Error> {
-        let (_eloop, http) = transports::http::Http::new(url)?;
-        let http = Arc::new(http);
-        let certifier = certifier.map(|address| {
-            let eth = Eth::new(http.clone());
-            let address = (*address).into();
-            contract::Contract::from_json(eth, address, include_bytes!("./abi/MultiCertifier.json")).expect("Valid ABI provided; qed")
-        });
+    ///
+    /// Requires at least one node url (or two in `cross_check` mode).
+    pub fn new(
+        endpoints: &[Endpoint],
+        cache: CacheOptions,
+        state_block: StateBlock,
+        cross_check: bool,
+    ) -> Result<Self, web3::Error> {
+        if endpoints.is_empty() || (cross_check && endpoints.len() < 2) {
+            return Err("Not enough blockchain nodes configured.".into());
+        }
+
+        let mut nodes = Vec::with_capacity(endpoints.len());
+        let mut eloops = Vec::with_capacity(endpoints.len());
+        for endpoint in endpoints {
+            let (eloop, transport) = transport::connect(endpoint)?;
+            nodes.push(Node {
+                url: endpoint.to_string(),
+                transport: transport.clone(),
+                web3: Web3::new(transport),
+            });
+            eloops.push(eloop);
+        }
 
         Ok(Blockchain {
-            web3: Web3::new(http),
-            _eloop,
+            nodes: Arc::new(nodes),
+            _eloops: eloops,
+            cross_check,
             latest_block: Default::default(),
-            cached_balance_and_nonce: Default::default(),
-            cached_certification: Default::default(),
-            certifier,
+            cached_balance_and_nonce: Arc::new(Cache::new(cache.capacity, cache.balance_ttl)),
+            in_flight: Cache::new(cache.capacity, cache.in_flight_ttl),
+            state_block,
         })
     }
 }
 
-impl<T: Transport> Blockchain<T> where
+impl<T: Transport + Send + Sync + 'static> Blockchain<T> where
     T::Out: Send + 'static,
 {
     fn update_latest_block(&self, new: BlockNumber) {
         *self.latest_block.write() = new;
-        self.cached_balance_and_nonce.write().clear();
-        self.cached_certification.write().clear();
+    }
+
+    /// Updates cached sender state after the transaction was submitted to the network.
+    ///
+    /// The cached nonce is bumped and the balance is reduced by the maximal cost of the transaction.
+    pub fn note_submitted(&self, transaction: &Transaction) {
+        let tx: UnverifiedTransaction = match UntrustedRlp::new(transaction.rlp()).as_val() {
+            Ok(tx) => tx,
+            Err(err) => {
+                warn!("[{:?}] Unable to decode submitted transaction: {:?}", transaction.hash(), err);
+                return;
+            },
+        };
+
+        self.in_flight.upsert(*transaction.sender(), BTreeSet::new(), |nonces| {
+            nonces.insert(tx.nonce);
+        });
+
+        let cost = tx.value.saturating_add(tx.gas.saturating_mul(tx.gas_price));
+        self.cached_balance_and_nonce.update(transaction.sender(), |&mut (ref mut balance, ref mut nonce)| {
+            *balance = if *balance > cost { *balance - cost } else { U256::zero() };
+            if *nonce <= tx.nonce {
+                *nonce = tx.nonce + U256::one();
+            }
+        });
     }
 
     /// Returns current latest block.
@@ -73,51 +185,171 @@ impl<T: Transport> Blockchain<T> where
         *self.latest_block.read()
     }
 
+    /// Returns the next nonce of given sender taking into account
+    /// transactions already submitted by the scheduler, but not yet reflected in `state_nonce`.
+    pub fn next_nonce(&self, sender: &Address, state_nonce: U256) -> U256 {
+        let mut nonce = state_nonce;
+        self.in_flight.update(sender, |nonces| {
+            // Prune transactions that are already included in the state.
+            *nonces = nonces.split_off(&state_nonce);
+            while nonces.contains(&nonce) {
+                nonce = nonce + U256::one();
+            }
+        });
+        nonce
+    }
+
     /// Queries the blockchain for given sender's balance and nonce.
     pub fn balance_and_nonce(&self, sender: Address) -> Box<Future<Item=BN, Error=web3::Error> + Send> {
         trace!("Fetching balance and nonce for {:?}", sender);
-        if let Some(bn) = self.cached_balance_and_nonce.read().get(&sender) {
+        if let Some(bn) = self.cached_balance_and_nonce.get(&sender) {
             trace!("Returning cached result for {:?} = {:?}", sender, bn);
-            return Box::new(future::ok(bn.clone()));
+            return Box::new(future::ok(bn));
         }
 
-        let address = (*sender).into();
-        let balance = self.web3.eth().balance(address, None).map(|balance| (*balance).into());
-        let nonce = self.web3.eth().transaction_count(address, None).map(|nonce| (*nonce).into());
+        let address: web3::types::Address = (*sender).into();
+        let block: web3::types::BlockNumber = self.state_block.into();
+        let query = self.query(move |node| {
+            let balance = node.web3.eth().balance(address, Some(block.clone())).map(|balance| (*balance).into());
+            let nonce = node.web3.eth().transaction_count(address, Some(block.clone())).map(|nonce| (*nonce).into());
+            Box::new(balance.join(nonce)) as Query<BN, web3::Error>
+        });
 
         let cbn = self.cached_balance_and_nonce.clone();
-        Box::new(balance.join(nonce).map(move |res| {
+        Box::new(query.map(move |res| {
             trace!("Got balance and nonce for {:?} = {:?}", sender, res);
-            cbn.write().insert(sender, res.clone());
+            cbn.insert(sender, res.clone());
             res
         }))
     }
 
-    /// Checks whether address is certified on blockchain.
-    pub fn is_certified(&self, sender: Address) -> Box<Future<Item=bool, Error=contract::Error> + Send> {
-        trace!("Checking certification status for {:?}", sender);
-        let certifier = match self.certifier {
-            None => return Box::new(future::ok(true)),
-            Some(ref certifier) => certifier,
-        };
+    /// Returns gas price suggested by the node.
+    pub fn gas_price(&self) -> Box<Future<Item=U256, Error=web3::Error> + Send> {
+        self.query(|node| {
+            Box::new(node.web3.eth().gas_price().map(|price| (*price).into())) as Query<U256, web3::Error>
+        })
+    }
 
-        if let Some(is_certified) = self.cached_certification.read().get(&sender) {
-            trace!("Returning cached result for {:?} = {:?}", sender, is_certified);
-            return Box::new(future::ok(*is_certified));
+    /// Returns gas prices of transactions included in given block.
+    pub fn block_gas_prices(&self, number: BlockNumber) -> Box<Future<Item=Vec<U256>, Error=web3::Error> + Send> {
+        self.query(move |node| {
+            let params = vec![format!("0x{:x}", number).into(), true.into()];
+            Box::new(node.transport.execute("eth_getBlockByNumber", params).and_then(move |block| {
+                let transactions = block.get("transactions").and_then(|txs| txs.as_array()).cloned().unwrap_or_default();
+                transactions.iter()
+                    .map(|tx| tx.get("gasPrice")
+                        .and_then(|price| price.as_str())
+                        .and_then(|price| price.trim_left_matches("0x").parse().ok())
+                        .ok_or_else(|| format!("Invalid transaction in block {}: {:?}", number, tx).into())
+                    )
+                    .collect()
+            })) as Query<Vec<U256>, web3::Error>
+        })
+    }
+
+    /// Estimates gas required by a transaction (at the latest block).
+    pub fn estimate_gas(
+        &self,
+        sender: Address,
+        to: Option<Address>,
+        value: U256,
+        data: Vec<u8>,
+    ) -> Box<Future<Item=U256, Error=web3::Error> + Send> {
+        let mut request = json!({
+            "from": format!("0x{}", sender.to_hex()),
+            "value": format!("0x{:x}", value),
+            "data": format!("0x{}", data.to_hex()),
+        });
+        if let Some(to) = to {
+            request["to"] = format!("0x{}", to.to_hex()).into();
         }
+        self.query(move |node| {
+            Box::new(node.transport.execute("eth_estimateGas", vec![request.clone()]).and_then(|gas| {
+                gas.as_str()
+                    .and_then(|gas| gas.trim_left_matches("0x").parse().ok())
+                    .ok_or_else(|| format!("Invalid gas estimate: {:?}", gas).into())
+            })) as Query<U256, web3::Error>
+        })
+    }
 
-        let address: web3::types::Address = (*sender).into();
-        let cc = self.cached_certification.clone();
-        Box::new(
-            certifier.query("certified", (address, ), None, Default::default(), None).map(move |res: bool| {
-            trace!("Got certification status for {:?} = {:?}", sender, res);
-              cc.write().insert(sender, res);
-              res
-            })
-        )
+    /// Calls a constant `function` of given contract passing `sender` as the only parameter.
+    pub fn call<R>(
+        &self,
+        contract: Address,
+        abi: Arc<ethabi::Contract>,
+        function: Arc<String>,
+        sender: Address,
+    ) -> Box<Future<Item=R, Error=contract::Error> + Send> where
+        T: Clone,
+        R: contract::tokens::Detokenize + PartialEq + fmt::Debug + Send + 'static,
+    {
+        trace!("Calling {} of {:?} for {:?}", function, contract, sender);
+        let contract: web3::types::Address = (*contract).into();
+        let sender: web3::types::Address = (*sender).into();
+        self.query(move |node| {
+            let eth = Eth::new(node.transport.clone());
+            let instance = contract::Contract::new(eth, contract.clone(), (*abi).clone());
+            Box::new(instance.query(&function, (sender.clone(), ), None, Default::default(), None)) as Query<R, contract::Error>
+        })
     }
+
+    /// Runs given query against the nodes (with failover or cross-checking).
+    fn query<R, E, F>(&self, query: F) -> Query<R, E> where
+        R: PartialEq + fmt::Debug + Send + 'static,
+        E: From<&'static str> + fmt::Debug + Send + 'static,
+        F: Fn(&Node<T>) -> Query<R, E> + Send + Sync + 'static,
+    {
+        if !self.cross_check {
+            return failover(self.nodes.clone(), 0, Arc::new(query));
+        }
+
+        let nodes = self.nodes.clone();
+        let queries = nodes.iter().map(|node| query(node).then(Ok::<_, E>)).collect::<Vec<_>>();
+        Box::new(future::join_all(queries).and_then(move |results| {
+            let mut responses = Vec::with_capacity(2);
+            for (node, result) in nodes.iter().zip(results) {
+                match result {
+                    Ok(result) => responses.push((&node.url, result)),
+                    Err(err) => warn!("Query to {} failed: {:?}", node.url, err),
+                }
+                if responses.len() == 2 {
+                    break;
+                }
+            }
+
+            if responses.len() < 2 {
+                return Err("Not enough blockchain nodes responded to cross-check the state.".into());
+            }
+            let (second, first) = (responses.pop().expect("Two responses; qed"), responses.pop().expect("Two responses; qed"));
+            if first.1 == second.1 {
+                return Ok(first.1);
+            }
+            warn!("Inconsistent state: {} returned {:?}, {} returned {:?}", first.0, first.1, second.0, second.1);
+            Err("Inconsistent state returned by blockchain nodes.".into())
+        }))
+    }
+}
+
+/// Runs the query against subsequent nodes (starting from `index`) until one succeeds.
+fn failover<T, R, E, F>(nodes: Arc<Vec<Node<T>>>, index: usize, query: Arc<F>) -> Query<R, E> where
+    T: Transport + Send + Sync + 'static,
+    R: Send + 'static,
+    E: fmt::Debug + Send + 'static,
+    F: Fn(&Node<T>) -> Query<R, E> + Send + Sync + 'static,
+{
+    let result = query(&nodes[index]);
+    Box::new(result.or_else(move |err| {
+        if index + 1 >= nodes.len() {
+            return future::Either::A(future::err(err));
+        }
+        warn!("Query to {} failed: {:?}. Trying {}", nodes[index].url, err, nodes[index + 1].url);
+        future::Either::B(failover(nodes, index + 1, query))
+    }))
 }
 
+/// Latest block reported by a node (`None` if the node failed or timed out).
+type Head = Box<Future<Item=Option<BlockNumber>, Error=()>>;
+
 /// Blockchain updater.
 /// Responsible for feeding in latest block number to blockchain structure and to a returned stream.
 pub struct Updater {
@@ -134,25 +366,46 @@ impl Updater {
     }
 
     /// Starts the blockchain updater.
-    /// This method will block until indefinitely.
-    pub fn run(self, transport: TransportType) -> Result<(), web3::Error> {
-        match transport {
-            TransportType::Ipc(path) => {
-                let (_eloop, ipc) = transports::ipc::Ipc::new(&path)?;
-                self.run_internal(ipc)
-            },
-            TransportType::Http(url) => {
-                let (_eloop, http) = transports::http::Http::new(&url)?;
-                self.run_internal(http)
-            }
+    /// The latest block is the highest block reported by any of the nodes
+    /// (nodes are polled concurrently, nodes not responding within `HEAD_TIMEOUT_MS` are skipped).
+    /// This method will block until shutdown is requested via `Control`.
+    /// The returned stream of block numbers ends afterwards.
+    pub fn run<I: IntoIterator<Item=Endpoint>>(self, endpoints: I, control: Arc<Control>) -> Result<(), web3::Error> {
+        let timer = transport::timer();
+        let mut eloops = Vec::new();
+        let mut heads = Vec::new();
+        for endpoint in endpoints {
+            let (eloop, transport) = transport::connect(&endpoint)?;
+            heads.push(Self::head(endpoint.to_string(), transport, timer.clone()));
+            eloops.push(eloop);
         }
+        self.run_internal(heads, control);
         Ok(())
     }
 
-    fn run_internal<T: Transport>(mut self, transport: T) {
-        info!("Starting blockchain updater.");
+    fn head<T: Transport + 'static>(name: String, transport: T, timer: Timer) -> Box<Fn() -> Head> {
         let web3 = Web3::new(transport);
-        
+        let name = Arc::new(name);
+        Box::new(move || {
+            let (name, timeout_name) = (name.clone(), name.clone());
+            let block_number = web3.eth().block_number().then(move |result| match result {
+                Ok(block_number) => Ok::<_, ()>(Some(block_number.low_u64())),
+                Err(err) => {
+                    warn!("Cannot fetch latest block from {}: {:?}", name, err);
+                    Ok(None)
+                },
+            });
+            let timeout = timer.sleep(time::Duration::from_millis(HEAD_TIMEOUT_MS)).then(move |_| {
+                warn!("Timeout fetching latest block from {}.", timeout_name);
+                Ok::<_, ()>(None)
+            });
+            Box::new(block_number.select(timeout).map(|(block, _)| block).map_err(|(err, _)| err))
+        })
+    }
+
+    fn run_internal(mut self, heads: Vec<Box<Fn() -> Head>>, control: Arc<Control>) {
+        info!("Starting blockchain updater.");
+
         let mut last_block = None;
         let mut update = |block_number, last_block: &mut Option<BlockNumber>| {
             trace!("Updating latest block number: {}", block_number);
@@ -163,15 +416,16 @@ impl Updater {
             }
         };
 
-        loop {
-            match web3.eth().block_number().wait() {
-                Err(err) => {
-                    warn!("Cannot fetch latest block: {:?}", err);
+        while !control.is_shutting_down() {
+            let blocks = future::join_all(heads.iter().map(|head| head()).collect::<Vec<_>>()).wait()
+                .expect("Head queries never fail; qed");
+            match blocks.into_iter().filter_map(|block| block).max() {
+                None => {
+                    warn!("Cannot fetch latest block from any node.");
                 },
-                Ok(block_number) => {
-                    let block_number = block_number.low_u64();
+                Some(block_number) => {
                     match last_block {
-                        Some(block) if block != block_number => update(block_number, &mut last_block),
+                        Some(block) if block < block_number => update(block_number, &mut last_block),
                         None => update(block_number, &mut last_block),
                         _ => {},
                     }
@@ -179,5 +433,7 @@ impl Updater {
             }
             thread::sleep(time::Duration::from_millis(100));
         }
+
+        info!("Blockchain updater stopped.");
     }
 }
@@ -0,0 +1,105 @@
+//! Bounded LRU cache with time-to-live.
+
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+
+/// A thread-safe LRU cache, where entries expire after given TTL.
+#[derive(Debug)]
+pub struct Cache<K: Hash + Eq, V> {
+    entries: Mutex<LruCache<K, (V, Instant)>>,
+    ttl: Duration,
+}
+
+impl<K: Hash + Eq, V: Clone> Cache<K, V> {
+    /// Creates new cache with given capacity and TTL.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Cache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    /// Returns a cached value if it's not expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Inserts a value to the cache.
+    pub fn insert(&self, key: K, value: V) {
+        self.entries.lock().insert(key, (value, Instant::now()));
+    }
+
+    /// Modifies existing (not expired) entry in place or inserts `default` modified by `f`.
+    pub fn upsert<F: FnOnce(&mut V)>(&self, key: K, default: V, f: F) {
+        let mut entries = self.entries.lock();
+        if let Some(&mut (ref mut value, inserted)) = entries.get_mut(&key) {
+            if inserted.elapsed() < self.ttl {
+                return f(value);
+            }
+        }
+
+        let mut value = default;
+        f(&mut value);
+        entries.insert(key, (value, Instant::now()));
+    }
+
+    /// Modifies existing (not expired) entry in place.
+    /// The entry keeps its original expiration time.
+    pub fn update<F: FnOnce(&mut V)>(&self, key: &K, f: F) {
+        if let Some(&mut (ref mut value, inserted)) = self.entries.lock().get_mut(key) {
+            if inserted.elapsed() < self.ttl {
+                f(value);
+            }
+        }
+    }
+
+    fn get_at(&self, key: &K, now: Instant) -> Option<V> {
+        let mut entries = self.entries.lock();
+        match entries.get_mut(key) {
+            None => return None,
+            Some(&mut (ref value, inserted)) if now.duration_since(inserted) < self.ttl => {
+                return Some(value.clone());
+            },
+            Some(_) => {},
+        }
+
+        entries.remove(key);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::Cache;
+
+    #[test]
+    fn should_evict_least_recently_used() {
+        let cache = Cache::new(2, Duration::from_secs(10));
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.insert(3, "c");
+
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some("c"));
+    }
+
+    #[test]
+    fn should_expire_and_update_entries() {
+        let cache = Cache::new(2, Duration::from_secs(10));
+        cache.insert(1, 5);
+        cache.update(&1, |v| *v += 1);
+        cache.update(&2, |v| *v += 1);
+
+        let now = Instant::now();
+        assert_eq!(cache.get_at(&1, now), Some(6));
+        assert_eq!(cache.get_at(&2, now), None);
+        assert_eq!(cache.get_at(&1, now + Duration::from_secs(11)), None);
+        assert_eq!(cache.get_at(&1, now), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! Detection of the chain id and verification that all nodes are connected to the same chain.
+
+use std::sync::Arc;
+use std::{thread, time};
+
+use futures::Future;
+use web3::{self, Transport, transports};
+
+use submitter::Control;
+use transport::{self, Endpoint};
+
+/// A node queried for its chain id.
+struct Node {
+    name: String,
+    chain_id: Box<Fn() -> Result<u64, web3::Error>>,
+}
+
+/// Verifies chain id reported by the nodes.
+pub struct Monitor {
+    nodes: Vec<Node>,
+    _eloops: Vec<transports::EventLoopHandle>,
+}
+
+impl Monitor {
+    /// Connects to given nodes.
+    pub fn new<I: IntoIterator<Item=Endpoint>>(endpoints: I) -> Result<Self, web3::Error> {
+        let mut nodes = Vec::new();
+        let mut eloops = Vec::new();
+        for endpoint in endpoints {
+            let (eloop, transport) = transport::connect(&endpoint)?;
+            nodes.push(Self::node(endpoint.to_string(), transport));
+            eloops.push(eloop);
+        }
+
+        Ok(Monitor {
+            nodes,
+            _eloops: eloops,
+        })
+    }
+
+    fn node<T: Transport + 'static>(name: String, transport: T) -> Node {
+        Node {
+            name,
+            chain_id: Box::new(move || chain_id(&transport)),
+        }
+    }
+
+    /// Verifies that all responding nodes are connected to the `expected` chain.
+    /// If `expected` is `None` the chain id of the first responding node is used.
+    ///
+    /// Returns the chain id or an error listing the mismatched nodes.
+    pub fn verify(&self, expected: Option<u64>) -> Result<u64, web3::Error> {
+        let mut chain_id = expected;
+        let mut mismatched = Vec::new();
+        for node in &self.nodes {
+            match ((node.chain_id)(), chain_id) {
+                (Err(err), _) => warn!("Unable to fetch chain id from {}: {:?}", node.name, err),
+                (Ok(id), None) => {
+                    info!("Detected chain id {} at {}", id, node.name);
+                    chain_id = Some(id);
+                },
+                (Ok(id), Some(expected)) if id != expected => {
+                    mismatched.push(format!("{} (chain id {})", node.name, id));
+                },
+                _ => {},
+            }
+        }
+
+        match chain_id {
+            None => Err("Unable to detect chain id: none of the nodes responded.".into()),
+            Some(id) if !mismatched.is_empty() => {
+                Err(format!("Nodes connected to a different chain than {}: {}", id, mismatched.join(", ")).into())
+            },
+            Some(id) => Ok(id),
+        }
+    }
+
+    /// Periodically re-verifies the nodes.
+    /// Submission is halted (see `Control::set_halted`) as long as any of the nodes reports a different chain.
+    ///
+    /// This method blocks indefinitely.
+    pub fn run(self, chain_id: u64, control: Arc<Control>, interval: time::Duration) {
+        loop {
+            thread::sleep(interval);
+            match self.verify(Some(chain_id)) {
+                Ok(_) if control.is_halted() => {
+                    info!("All nodes are connected to chain {} again.", chain_id);
+                    control.set_halted(false);
+                },
+                Err(err) => {
+                    if !control.is_halted() {
+                        error!("Halting submission: {:?}", err);
+                    }
+                    control.set_halted(true);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Fetches chain id of the node.
+///
+/// Falls back to `net_version` for nodes not supporting `eth_chainId`
+/// (note that network id and chain id are different on some chains).
+fn chain_id<T: Transport>(transport: &T) -> Result<u64, web3::Error> {
+    let chain_id = transport.execute("eth_chainId", vec![]).wait()
+        .ok()
+        .and_then(|value| value.as_str().and_then(|id| u64::from_str_radix(id.trim_left_matches("0x"), 16).ok()));
+    if let Some(chain_id) = chain_id {
+        return Ok(chain_id);
+    }
+
+    let version = transport.execute("net_version", vec![]).wait()?;
+    version.as_str()
+        .and_then(|version| version.parse().ok())
+        .ok_or_else(|| format!("Invalid net_version response: {:?}", version).into())
+}
@@ -0,0 +1,64 @@
+//! JSON-RPC client of a running scheduler.
+
+use futures::Future;
+use serde::Serialize;
+use serde_json::{self, Value};
+use web3::{self, Transport, transports};
+
+use database::Status;
+use transport::{self, Endpoint, NodeTransport};
+use types::{Bytes, Condition, TransactionId};
+
+/// Client of scheduler's public JSON-RPC methods (over HTTP(S) or IPC).
+pub struct Client {
+    transport: NodeTransport,
+    _eloop: transports::EventLoopHandle,
+}
+
+impl Client {
+    /// Creates new client of the scheduler listening at given URL (`http(s)://` or `ipc:///path`).
+    ///
+    /// API key can be passed as `apiKey` query parameter of the URL.
+    pub fn new(url: &str) -> Result<Self, web3::Error> {
+        let (eloop, transport) = transport::connect(&Endpoint::new(url, Default::default())?)?;
+        Ok(Client {
+            transport,
+            _eloop: eloop,
+        })
+    }
+
+    /// Schedules signed transaction for submission and returns its id.
+    ///
+    /// Chain id is required only for transactions without replay protection if the scheduler serves multiple chains.
+    pub fn schedule(&self, condition: &Condition, transaction: Bytes, chain_id: Option<u64>) -> Result<TransactionId, web3::Error> {
+        let mut params = vec![to_value(condition), to_value(&transaction)];
+        if let Some(chain_id) = chain_id {
+            params.push(chain_id.into());
+        }
+        let id = self.call("scheduleTransaction", params)?;
+        serde_json::from_value(id.clone()).ok()
+            .and_then(TransactionId::from_bytes)
+            .ok_or_else(|| format!("Invalid transaction id: {:?}", id).into())
+    }
+
+    /// Cancels scheduled transaction.
+    pub fn cancel(&self, id: &TransactionId) -> Result<(), web3::Error> {
+        self.call("cancel", vec![to_value(&id.to_bytes())]).map(|_| ())
+    }
+
+    /// Returns status of scheduled transaction.
+    pub fn status(&self, id: &TransactionId) -> Result<Status, web3::Error> {
+        let status = self.call("status", vec![to_value(&id.to_bytes())])?;
+        serde_json::from_value(status.clone())
+            .map_err(|_| format!("Invalid status: {:?}", status).into())
+    }
+
+    fn call(&self, method: &str, params: Vec<Value>) -> Result<Value, web3::Error> {
+        trace!("Calling {}: {:?}", method, params);
+        self.transport.execute(method, params).wait()
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).expect("Conditions and bytes serialization is infallible; qed")
+}
@@ -2,15 +2,20 @@
 
 use std::collections::btree_map::Entry;
 use std::collections::hash_map;
-use std::collections::{HashMap, BTreeMap};
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::fmt;
+use std::hash::Hash;
 use std::io::{Read, Write, Seek};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::{io, fs, mem};
+use std::time::{Duration, SystemTime};
+use std::{io, fs, mem, vec};
 
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
+use notify::Notify;
+use options::{ApiKeys, SharedOptions};
 use types::{BlockNumber, Transaction, Address, H256};
 
 mod error {
@@ -25,6 +30,10 @@ mod error {
                 description("Sender already scheduled.")
                 display("Sender already scheduled.")
             }
+            Locked(path: String) {
+                description("Database is used by another process.")
+                display("Database at {} is used by another process.", path)
+            }
         }
     }
 }
@@ -35,22 +44,59 @@ pub use self::error::*;
 /// Each block has a separate instance of `BlockDatabase`.
 ///
 /// The database should store only valid transactions.
+///
+/// Transactions are drained in two phases: `<target>.txs` file is first renamed to `<target>.claimed`,
+/// then the transactions are submitted and once all of them are acknowledged (see `Claimed::acknowledge`)
+/// the file is renamed to `<target>.done` (and removed later, see `collect_garbage`).
+/// Unacknowledged transactions of claimed files are restored when the database is re-opened.
+///
+/// The directory is locked (see `Lock`) while the database is open.
 #[derive(Debug)]
 pub struct Database {
     path: PathBuf,
-    senders: Arc<RwLock<HashMap<Address, usize>>>,
+    counters: Arc<RwLock<Counters>>,
     blocks: RwLock<BTreeMap<BlockNumber, BlockDatabase>>,
     max_txs_per_sender: usize,
+    options: Option<Arc<SharedOptions>>,
+    changes: Option<Arc<Notify>>,
+    _lock: Lock,
 }
 
 impl Database {
     const EXT: &'static str = "txs";
 
     /// Open and load existing database in given directory.
+    ///
+    /// Transactions claimed but not acknowledged before are restored.
     pub fn open<T: AsRef<Path>>(path: T, max_txs_per_sender: usize) -> Result<Self> {
         fs::create_dir_all(&path)?;
+        let lock = Lock::acquire(path.as_ref(), false)?;
+
+        // Restore claims first, so that restored transactions are loaded below.
+        for entry in fs::read_dir(&path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) == Some(Claim::EXT) {
+                if let Err(err) = Claim::restore(&path) {
+                    warn!("Unable to restore claimed transactions at {}: {:?}", path.display(), err);
+                }
+            }
+        }
+
+        Self::load(path, max_txs_per_sender, lock, false)
+    }
+
+    /// Opens existing database in given directory for reading only.
+    ///
+    /// Nothing is written to the directory: claimed transactions are not restored
+    /// (and not included in the database) and modifications fail.
+    pub fn open_read_only<T: AsRef<Path>>(path: T) -> Result<Self> {
+        let lock = Lock::acquire(path.as_ref(), true)?;
+        Self::load(path, usize::max_value(), lock, true)
+    }
+
+    fn load<T: AsRef<Path>>(path: T, max_txs_per_sender: usize, lock: Lock, read_only: bool) -> Result<Self> {
         let mut blocks = BTreeMap::new();
-        let mut senders = HashMap::new();
+        let mut counters = Counters::default();
 
         // Re-open all existing block database that are found
         for entry in fs::read_dir(&path)? {
@@ -63,7 +109,7 @@ impl Database {
             if let Some(Self::EXT) = extension {
                 let file_stem = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok());
                 if let Some(number) = file_stem {
-                    match BlockDatabase::open(&path, &mut senders) {
+                    match BlockDatabase::open(&path, number, &mut counters, read_only) {
                         Ok(block) => {
                             blocks.insert(number, block);
                         },
@@ -77,15 +123,66 @@ impl Database {
 
         Ok(Database {
             path: path.as_ref().to_owned(),
-            senders: Arc::new(RwLock::new(senders)),
+            counters: Arc::new(RwLock::new(counters)),
             blocks: RwLock::new(blocks),
             max_txs_per_sender,
+            options: None,
+            changes: None,
+            _lock: lock,
         })
     }
 
-    /// Returns number of transactions already scheduled from given sender.
+    /// Returns true if the database in given directory holds scheduled or claimed transactions.
+    ///
+    /// The directory is neither locked nor modified.
+    pub fn has_transactions<T: AsRef<Path>>(path: T) -> Result<bool> {
+        if !path.as_ref().is_dir() {
+            return Ok(false);
+        }
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let path = entry.path();
+            let extension = path.extension().and_then(|s| s.to_str());
+            if (extension == Some(Self::EXT) || extension == Some(Claim::EXT)) && entry.metadata()?.len() > 0 {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Notify given `Notify` whenever transactions are inserted or removed.
+    pub fn with_changes(mut self, changes: Arc<Notify>) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    fn notify_changes(&self) {
+        if let Some(ref changes) = self.changes {
+            changes.notify();
+        }
+    }
+
+    /// Limit transactions of a single sender according to given options (see `Options::max_txs_per_sender`)
+    /// instead of the limit the database was opened with, so that it changes together with the options.
+    ///
+    /// Transactions already scheduled above a lowered limit are kept.
+    pub fn with_options(mut self, options: Arc<SharedOptions>) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Returns true if given sender can schedule another transaction.
     pub fn sender_allowed(&self, sender: &Address) -> bool {
-        *self.senders.read().get(sender).unwrap_or(&0) < self.max_txs_per_sender
+        let max_txs_per_sender = match self.options {
+            Some(ref options) => options.get().max_txs_per_sender,
+            None => self.max_txs_per_sender,
+        };
+        *self.counters.read().senders.get(sender).unwrap_or(&0) < max_txs_per_sender
+    }
+
+    /// Returns number of transactions scheduled with given API key id.
+    pub fn api_key_count(&self, api_key: &str) -> usize {
+        *self.counters.read().api_keys.get(api_key).unwrap_or(&0)
     }
 
     /// Inserts new transactions to the store.
@@ -95,17 +192,17 @@ impl Database {
             return Err(ErrorKind::SenderExists.into());
         }
 
-        Senders::increment(&mut self.senders.write(), transaction.sender());
-        let mut blocks = self.blocks.write();
-
-        match blocks.entry(block_number) {
+        self.counters.write().add(block_number, &transaction);
+        let result = match self.blocks.write().entry(block_number) {
             Entry::Vacant(vacant) => {
                 let path = self.path.join(format!("{}.{}", block_number, Self::EXT));
                 let db = BlockDatabase::new(&path)?;
                 vacant.insert(db).insert(transaction)
             },
             Entry::Occupied(ref mut db) => db.get_mut().insert(transaction),
-        }
+        };
+        self.notify_changes();
+        result
     }
 
     /// Removes a transaction from the store.
@@ -114,13 +211,53 @@ impl Database {
         if let Some(ref mut block) = self.blocks.write().get_mut(block_number) {
             if let Some(transaction) = block.remove(hash)? {
                 debug!("[:?] Removed from: {}", block_number);
-                Senders::decrement(&mut self.senders.write(), transaction.sender());
+                self.counters.write().remove(*block_number, &transaction);
+                self.notify_changes();
                 return Ok(Some(transaction))
             }
         }
         Ok(None)
     }
 
+    /// Removes all transactions of given sender from the store.
+    pub fn remove_sender(&self, sender: &Address) -> Result<Vec<Transaction>> {
+        trace!("Attempting to remove all transactions from: {:?}", sender);
+        let mut removed = Vec::new();
+        let mut blocks = self.blocks.write();
+        let mut counters = self.counters.write();
+        for (number, block) in blocks.iter_mut() {
+            let txs = block.retain(|tx| tx.sender() != sender)?;
+            if !txs.is_empty() {
+                debug!("Removed {} transactions of {:?} from: {}", txs.len(), sender, number);
+            }
+            for transaction in &txs {
+                counters.remove(*number, transaction);
+            }
+            removed.extend(txs);
+        }
+
+        self.notify_changes();
+        Ok(removed)
+    }
+
+    /// Returns statistics of currently scheduled transactions.
+    pub fn stats(&self) -> Stats {
+        let blocks = self.blocks.read();
+        let counters = self.counters.read();
+        Stats {
+            transactions: counters.senders.values().sum(),
+            senders: counters.senders.len(),
+            targets: blocks.len(),
+            earliest: blocks.keys().next().cloned(),
+            latest: blocks.keys().next_back().cloned(),
+        }
+    }
+
+    /// Returns the earliest block (or timestamp) with scheduled transactions.
+    pub fn earliest(&self) -> Option<BlockNumber> {
+        self.blocks.read().keys().next().cloned()
+    }
+
     /// Returns true if there are any transactions scheduled for given block.
     pub fn has(&self, block_number: &BlockNumber) -> bool {
         match self.blocks.read().keys().next() {
@@ -129,31 +266,444 @@ impl Database {
         }
     }
 
+    /// Returns status of transaction with given hash scheduled for given block (or timestamp).
+    ///
+    /// Scheduled transactions are found in memory, files are read only for drained transactions.
+    pub fn status(&self, block_number: BlockNumber, hash: &H256) -> Result<Status> {
+        if self.counters.read().is_scheduled(block_number, hash) {
+            return Ok(Status::Scheduled);
+        }
+
+        let contains = |extension: &str| -> Result<bool> {
+            let path = self.path.join(format!("{}.{}", block_number, extension));
+            if !path.exists() {
+                return Ok(false);
+            }
+            let mut file = fs::File::open(path)?;
+            let found = TransactionsIterator::new(&mut file)?.any(|tx| tx.hash() == hash);
+            Ok(found)
+        };
+        let acknowledged = || -> Result<bool> {
+            let path = self.path.join(format!("{}.{}", block_number, Claim::ACKS_EXT));
+            let mut content = Vec::new();
+            match fs::File::open(path) {
+                Ok(mut file) => file.read_to_end(&mut content)?,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err.into()),
+            };
+            Ok(content.chunks(32).any(|ack| ack == &**hash))
+        };
+
+        if contains(Claim::EXT)? {
+            Ok(if acknowledged()? { Status::Submitted } else { Status::Submitting })
+        } else if contains(Claim::DONE_EXT)? {
+            Ok(Status::Submitted)
+        } else {
+            Ok(Status::Unknown)
+        }
+    }
+
+    /// Returns all scheduled transactions ordered by the target.
+    pub fn transactions(&self) -> Result<Vec<(BlockNumber, Transaction)>> {
+        let mut transactions = Vec::new();
+        for (number, block) in self.blocks.write().iter_mut() {
+            let (txs, _) = block.read()?;
+            transactions.extend(txs.into_iter().map(|tx| (*number, tx)));
+        }
+        Ok(transactions)
+    }
+
+    /// Checks that all records are readable and that stored hashes and senders match the transactions.
+    pub fn verify(&self) -> Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        for (number, block) in self.blocks.write().iter_mut() {
+            let (transactions, unread) = block.read()?;
+            issues.extend(transactions.iter().filter_map(|tx| Issue::check(*number, tx)));
+            if unread > 0 {
+                issues.push(Issue::Corrupted { target: *number, bytes: unread });
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Removes records reported by `verify` and files without any transactions.
+    ///
+    /// Returns the removed issues.
+    pub fn compact(&self) -> Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        let mut empty = Vec::new();
+        let mut blocks = self.blocks.write();
+        for (number, block) in blocks.iter_mut() {
+            let (transactions, unread) = block.read()?;
+            let found = issues.len();
+            let mut valid = Vec::with_capacity(transactions.len());
+            for transaction in transactions {
+                match Issue::check(*number, &transaction) {
+                    Some(issue) => {
+                        self.counters.write().remove(*number, &transaction);
+                        issues.push(issue);
+                    },
+                    None => valid.push(transaction),
+                }
+            }
+            if unread > 0 {
+                issues.push(Issue::Corrupted { target: *number, bytes: unread });
+            }
+
+            if valid.is_empty() {
+                empty.push(*number);
+            } else if issues.len() > found {
+                block.rewrite(valid)?;
+            }
+        }
+
+        for number in empty {
+            if let Some(block) = blocks.remove(&number) {
+                debug!("Removing empty db file at {}", block.path.display());
+                fs::remove_file(&block.path)?;
+            }
+        }
+        self.notify_changes();
+        Ok(issues)
+    }
+
+    /// Removes files of acknowledged transactions older than given retention period.
+    ///
+    /// Returns number of removed files.
+    pub fn collect_garbage(&self, retention: Duration) -> Result<usize> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let path = entry.path();
+            match path.extension().and_then(|s| s.to_str()) {
+                // `.old` files were created by previous versions.
+                Some(Claim::DONE_EXT) | Some("old") => {},
+                _ => continue,
+            }
+
+            let age = entry.metadata()?.modified()?;
+            if now.duration_since(age).map(|age| age > retention).unwrap_or(false) {
+                trace!("Removing acknowledged transactions at {}", path.display());
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            debug!("Removed {} files of acknowledged transactions from {}", removed, self.path.display());
+        }
+        Ok(removed)
+    }
+
+    /// Syncs all transaction files to disk.
+    pub fn flush(&self) -> Result<()> {
+        for block in self.blocks.read().values() {
+            block.file.sync_all()?;
+        }
+        Ok(())
+    }
+
     /// Drains transactions scheduled for submission up to given block number.
-    pub fn drain(&self, block_number: BlockNumber) -> Result<Option<TransactionsIterator>> {
+    ///
+    /// Drained transactions are claimed (see `Claimed`) and restored on restart until acknowledged.
+    pub fn drain(&self, block_number: BlockNumber) -> Result<Option<Drained>> {
         let blocks = {
             let mut blocks = self.blocks.write();
             let mut new = blocks.split_off(&(block_number + 1));
             mem::swap(&mut *blocks, &mut new);
             new
         };
-        let mut it = blocks.into_iter();
-        let mut tx_it = match it.next() {
-            None => return Ok(None),
-            Some((num, block)) => {
-                debug!("Draining transactions for block: {}", num);
-                block.drain(self.senders.clone())?
+        if blocks.is_empty() {
+            return Ok(None);
+        }
+
+        let mut claims = Vec::with_capacity(blocks.len());
+        for (num, block) in blocks {
+            debug!("Draining transactions for block: {}", num);
+            let (claim, transactions) = block.claim()?;
+            claims.push((num, claim, transactions));
+        }
+
+        Ok(Some(Drained {
+            claims: claims.into_iter(),
+            current: None,
+            counters: self.counters.clone(),
+        }))
+    }
+}
+
+/// Transactions drained from the database.
+#[derive(Debug)]
+pub struct Drained {
+    claims: vec::IntoIter<(BlockNumber, Arc<Claim>, Vec<Transaction>)>,
+    current: Option<(BlockNumber, Arc<Claim>, vec::IntoIter<Transaction>)>,
+    counters: Arc<RwLock<Counters>>,
+}
+
+impl Iterator for Drained {
+    type Item = Claimed;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((ref target, ref claim, ref mut transactions)) = self.current {
+                if let Some(transaction) = transactions.next() {
+                    self.counters.write().remove(*target, &transaction);
+                    return Some(Claimed { transaction, target: *target, claim: claim.clone() });
+                }
+            }
+
+            match self.claims.next() {
+                Some((target, claim, transactions)) => self.current = Some((target, claim, transactions.into_iter())),
+                None => return None,
             }
+        }
+    }
+}
+
+/// A transaction to submit.
+///
+/// Transactions drained from the database should be acknowledged after they are submitted,
+/// otherwise they are going to be restored on restart.
+#[derive(Debug, Clone)]
+pub struct Claimed {
+    transaction: Transaction,
+    target: BlockNumber,
+    claim: Arc<Claim>,
+}
+
+impl Claimed {
+    /// Returns the transaction.
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    /// Returns the block number (or timestamp) the transaction was scheduled for.
+    pub fn target(&self) -> BlockNumber {
+        self.target
+    }
+
+    /// Returns the transaction (without acknowledging it).
+    pub fn into_transaction(self) -> Transaction {
+        self.transaction
+    }
+
+    /// Marks the transaction as submitted, so that it's not restored on restart.
+    pub fn acknowledge(&self) {
+        if let Err(err) = self.claim.acknowledge(self.transaction.hash()) {
+            warn!("[{:?}] Unable to acknowledge transaction: {:?}", self.transaction.hash(), err);
+        }
+    }
+}
+
+/// A set of transactions claimed for submission (a `<target>.claimed` file).
+///
+/// Hashes of acknowledged transactions are appended to `<target>.acks` file.
+/// When all transactions are acknowledged the claimed file is renamed to `<target>.done`.
+#[derive(Debug)]
+pub struct Claim {
+    path: PathBuf,
+    state: Mutex<ClaimState>,
+}
+
+#[derive(Debug)]
+struct ClaimState {
+    pending: HashSet<H256>,
+    acks: Option<fs::File>,
+}
+
+impl Claim {
+    const EXT: &'static str = "claimed";
+    const ACKS_EXT: &'static str = "acks";
+    const DONE_EXT: &'static str = "done";
+
+    fn new(path: PathBuf, pending: HashSet<H256>) -> Result<Self> {
+        let claim = Claim {
+            path,
+            state: Mutex::new(ClaimState { pending, acks: None }),
         };
-        for (num, block) in it {
-            debug!("Draining transactions for block: {}", num);
-            tx_it.append(block.drain(self.senders.clone())?);
+
+        if claim.state.lock().pending.is_empty() {
+            claim.finish()?;
+        }
+        Ok(claim)
+    }
+
+    /// Acknowledges transaction with given hash.
+    fn acknowledge(&self, hash: &H256) -> Result<()> {
+        let mut state = self.state.lock();
+        if !state.pending.remove(hash) {
+            return Ok(());
+        }
+
+        if state.pending.is_empty() {
+            state.acks = None;
+            return self.finish();
+        }
+
+        if state.acks.is_none() {
+            state.acks = Some(fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(self.path.with_extension(Self::ACKS_EXT))?);
+        }
+        let acks = state.acks.as_mut().expect("File opened above; qed");
+        acks.write_all(&**hash)?;
+        acks.flush()?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        trace!("All transactions acknowledged: {}", self.path.display());
+        Self::done(&self.path)?;
+        match fs::remove_file(self.path.with_extension(Self::ACKS_EXT)) {
+            Err(ref err) if err.kind() != io::ErrorKind::NotFound => warn!("Unable to remove acks file: {:?}", err),
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Renames the claimed file to `<target>.done`.
+    ///
+    /// The modification time is set to the acknowledgement time (used by `Database::collect_garbage`).
+    fn done(path: &Path) -> Result<()> {
+        let done = path.with_extension(Self::DONE_EXT);
+        fs::rename(path, &done)?;
+        if let Err(err) = touch(&done) {
+            warn!("Unable to update modification time of {}: {:?}", done.display(), err);
+        }
+        Ok(())
+    }
+
+    /// Moves unacknowledged transactions of the claimed file back to the `<target>.txs` file.
+    ///
+    /// Transactions already present in the `<target>.txs` file are skipped, so the restore
+    /// can be repeated if it was interrupted.
+    fn restore(path: &Path) -> Result<()> {
+        let acks_path = path.with_extension(Self::ACKS_EXT);
+        let mut acks = HashSet::new();
+        if acks_path.exists() {
+            let mut content = Vec::new();
+            fs::File::open(&acks_path)?.read_to_end(&mut content)?;
+            // Incomplete hash might be written if the process died while acknowledging.
+            acks.extend(content.chunks(32).filter(|hash| hash.len() == 32).map(H256::from_slice));
         }
 
-        Ok(Some(tx_it))
+        let mut file = fs::File::open(path)?;
+        let transactions = TransactionsIterator::new(&mut file)?
+            .filter(|tx| !acks.contains(tx.hash()))
+            .collect::<Vec<_>>();
+
+        if !transactions.is_empty() {
+            info!("Restoring {} unacknowledged transactions from {}", transactions.len(), path.display());
+            let txs_path = path.with_extension(Database::EXT);
+            let mut all = match fs::File::open(&txs_path) {
+                Ok(mut file) => TransactionsIterator::new(&mut file)?.collect::<Vec<_>>(),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(err) => return Err(err.into()),
+            };
+            let existing = all.iter().map(|tx| *tx.hash()).collect::<HashSet<_>>();
+            all.extend(transactions.into_iter().filter(|tx| !existing.contains(tx.hash())));
+            write_atomically(&txs_path, &all)?;
+        }
+
+        Self::done(path)?;
+        if acks_path.exists() {
+            fs::remove_file(&acks_path)?;
+        }
+        Ok(())
     }
 }
 
+/// Status of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Waiting for submission.
+    Scheduled,
+    /// Drained from the database, but not acknowledged by any node yet.
+    Submitting,
+    /// Submitted and acknowledged by a node.
+    Submitted,
+    /// Not found (cancelled, never scheduled or removed after retention period).
+    Unknown,
+}
+
+/// Problem with a stored record found by `Database::verify`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// Unreadable bytes at the end of the file.
+    Corrupted {
+        /// Block number or timestamp of the file.
+        target: BlockNumber,
+        /// Number of unreadable bytes.
+        bytes: usize,
+    },
+    /// Stored RLP is not a valid signed transaction.
+    InvalidTransaction {
+        /// Block number or timestamp of the transaction.
+        target: BlockNumber,
+        /// Stored hash of the transaction.
+        hash: H256,
+        /// Decoding error.
+        error: String,
+    },
+    /// Stored hash or sender doesn't match the transaction.
+    Mismatch {
+        /// Block number or timestamp of the transaction.
+        target: BlockNumber,
+        /// Stored hash of the transaction.
+        hash: H256,
+    },
+}
+
+impl Issue {
+    fn check(target: BlockNumber, transaction: &Transaction) -> Option<Self> {
+        match Transaction::decode(transaction.rlp()) {
+            Err(error) => Some(Issue::InvalidTransaction { target, hash: *transaction.hash(), error }),
+            Ok(ref decoded) if decoded.hash() != transaction.hash() || decoded.sender() != transaction.sender() => {
+                Some(Issue::Mismatch { target, hash: *transaction.hash() })
+            },
+            Ok(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::Corrupted { target, bytes } =>
+                write!(f, "[{}] {} unreadable bytes at the end of file.", target, bytes),
+            Issue::InvalidTransaction { target, ref hash, ref error } =>
+                write!(f, "[{}] Invalid transaction {:?}: {}", target, hash, error),
+            Issue::Mismatch { target, ref hash } =>
+                write!(f, "[{}] Stored hash or sender doesn't match transaction {:?}.", target, hash),
+        }
+    }
+}
+
+/// Statistics of the transactions store.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    /// Number of scheduled transactions.
+    pub transactions: usize,
+    /// Number of distinct senders.
+    pub senders: usize,
+    /// Number of distinct blocks (or timestamps) with scheduled transactions.
+    pub targets: usize,
+    /// Earliest block (or timestamp) with scheduled transactions.
+    pub earliest: Option<BlockNumber>,
+    /// Latest block (or timestamp) with scheduled transactions.
+    pub latest: Option<BlockNumber>,
+}
+
+/// Set in the length prefix of records that are followed by an API key id.
+///
+/// Records written by previous versions contain the API key itself, it's replaced with its id when read.
+///
+/// Record layout: `u32 rlp length (| flag)`, `sender`, `hash`, `rlp`, [`u8 key length`, `key`].
+const API_KEY_FLAG: u32 = 1 << 31;
+
 /// A set of transactions to execute at particular block.
 #[derive(Debug)]
 struct BlockDatabase {
@@ -162,18 +712,18 @@ struct BlockDatabase {
 }
 
 impl BlockDatabase {
-    /// Open existing transactions store and load senders to given `HashMap`.
-    pub fn open<T: AsRef<Path>>(path: T, senders: &mut HashMap<Address, usize>) -> Result<Self> {
+    /// Open existing transactions store and populate given `Counters`.
+    pub fn open<T: AsRef<Path>>(path: T, number: BlockNumber, counters: &mut Counters, read_only: bool) -> Result<Self> {
         let mut file = fs::OpenOptions::new()
             .read(true)
-            .write(true)
+            .write(!read_only)
             .open(&path)?;
 
         trace!("Reading transactions from: {}", path.as_ref().display());
-        let mut it = TransactionsIterator::new(&mut file, IteratorMode::ReadOnly)?;
+        let mut it = TransactionsIterator::new(&mut file)?;
         while let Some(tx) = it.next() {
             trace!("Populating sender: {}", tx.sender());
-            Senders::increment(senders, tx.sender());
+            counters.add(number, &tx);
         }
         file.seek(io::SeekFrom::Start(0))?;
 
@@ -200,68 +750,200 @@ impl BlockDatabase {
     /// Inserts new transaction to the store.
     pub fn insert(&mut self, transaction: Transaction) -> Result<()> {
         trace!("[{:?}] Inserting to db.", transaction.hash());
+        self.file.write_all(&Self::encode(&transaction)?)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Encodes a single record.
+    fn encode(transaction: &Transaction) -> Result<Vec<u8>> {
         let rlp_len = transaction.rlp().len();
-        let mut vec = Vec::with_capacity(4 + 20 + 32 + rlp_len);
-        vec.write_u32::<LittleEndian>(rlp_len as u32)?;
+        let api_key = transaction.api_key().map(str::as_bytes);
+        let flags = if api_key.is_some() { API_KEY_FLAG } else { 0 };
+        let mut vec = Vec::with_capacity(4 + 20 + 32 + rlp_len + api_key.map_or(0, |key| 1 + key.len()));
+        vec.write_u32::<LittleEndian>(rlp_len as u32 | flags)?;
         vec.extend_from_slice(&**transaction.sender());
         vec.extend_from_slice(&**transaction.hash());
         vec.extend_from_slice(transaction.rlp());
-
-        self.file.write_all(&vec)?;
-        self.file.flush()?;
-        Ok(())
+        if let Some(key) = api_key {
+            assert!(key.len() <= u8::max_value() as usize, "API key ids have fixed length; qed");
+            vec.push(key.len() as u8);
+            vec.extend_from_slice(key);
+        }
+        Ok(vec)
     }
 
     /// Removes existing transaction from store
     pub fn remove(&mut self, hash: &H256) -> Result<Option<Transaction>> {
-        let (tx, position_before_tx, cursor)  = {
-            let mut it = TransactionsIterator::new(&mut self.file, IteratorMode::ReadOnly)?;
-            let tx = it.find(|tx| tx.hash() == hash);
+        let mut removed = self.retain(|tx| tx.hash() != hash)?;
+        Ok(removed.pop())
+    }
 
-            (tx, it.position_before_tx, it.content)
-        };
+    /// Keeps only transactions matching given predicate and returns the removed ones.
+    pub fn retain<F: Fn(&Transaction) -> bool>(&mut self, predicate: F) -> Result<Vec<Transaction>> {
+        let (kept, removed): (Vec<_>, Vec<_>) = TransactionsIterator::new(&mut self.file)?
+            .partition(|tx| predicate(tx));
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+
+        self.rewrite(kept)?;
+        Ok(removed)
+    }
+
+    /// Returns all readable transactions and the number of unreadable bytes at the end of the file.
+    fn read(&mut self) -> Result<(Vec<Transaction>, usize)> {
+        let mut it = TransactionsIterator::new(&mut self.file)?;
+        let transactions = it.by_ref().collect();
+        Ok((transactions, it.unread()))
+    }
+
+    /// Replaces content of the file with given transactions.
+    ///
+    /// The file is replaced atomically, either all or none of the changes are visible after a crash.
+    fn rewrite(&mut self, transactions: Vec<Transaction>) -> Result<()> {
+        let mut file = write_atomically(&self.path, &transactions)?;
+        file.seek(io::SeekFrom::End(0))?;
+        self.file = file;
+        Ok(())
+    }
 
-        let tx = match tx {
-            Some(tx) => tx,
-            None => return Ok(None),
+    /// Claims all transactions of this block for submission.
+    fn claim(mut self) -> Result<(Arc<Claim>, Vec<Transaction>)> {
+        trace!("Claiming transactions from: {}", self.path.display());
+        let transactions = TransactionsIterator::new(&mut self.file)?.collect::<Vec<_>>();
+        let path = self.path.with_extension(Claim::EXT);
+        self.file.sync_all()?;
+        fs::rename(&self.path, &path)?;
+
+        let pending = transactions.iter().map(|tx| *tx.hash()).collect();
+        Ok((Arc::new(Claim::new(path, pending)?), transactions))
+    }
+}
+
+/// Writes given transactions to a temporary file and renames it to `path` once synced.
+///
+/// Returns the file opened for reading and writing.
+fn write_atomically(path: &Path, transactions: &[Transaction]) -> Result<fs::File> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    for transaction in transactions {
+        file.write_all(&BlockDatabase::encode(transaction)?)?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_dir(path)?;
+    Ok(file)
+}
+
+/// Exclusive lock of a database directory, released when dropped (or when the process exits).
+///
+/// Prevents opening the same database by the scheduler and the offline `db` commands at the same time.
+#[derive(Debug)]
+struct Lock {
+    _file: Option<fs::File>,
+}
+
+impl Lock {
+    const FILE: &'static str = "LOCK";
+
+    /// Locks given directory.
+    ///
+    /// In read-only mode the lock file is not created (a directory without the file is not locked).
+    fn acquire(dir: &Path, read_only: bool) -> Result<Self> {
+        let path = dir.join(Self::FILE);
+        let file = if read_only {
+            if !dir.is_dir() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found", dir.display())).into());
+            }
+            match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Lock { _file: None }),
+                Err(err) => return Err(err.into()),
+            }
+        } else {
+            fs::OpenOptions::new().read(true).write(true).create(true).open(&path)?
         };
 
-        // go back before the transaction
-        self.file.seek(io::SeekFrom::Start(position_before_tx))?;
-        // write back
-        let position_after_tx = cursor.position() as usize;
-        let mut content = cursor.into_inner();
-        let new_length = content.len() - (position_after_tx - position_before_tx as usize);
-        self.file.write_all(&mut content[position_after_tx.. ])?;
-        self.file.set_len(new_length as u64)?;
-        self.file.flush()?;
-        Ok(Some(tx))
+        if !try_lock(&file)? {
+            return Err(ErrorKind::Locked(dir.display().to_string()).into());
+        }
+        Ok(Lock { _file: Some(file) })
     }
+}
+
+/// Tries to lock given file exclusively, returns false if it's locked already.
+#[cfg(unix)]
+fn try_lock(file: &fs::File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    match unsafe { ::libc::flock(file.as_raw_fd(), ::libc::LOCK_EX | ::libc::LOCK_NB) } {
+        0 => Ok(true),
+        _ => match io::Error::last_os_error() {
+            ref err if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            err => Err(err),
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &fs::File) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Sets modification time of given file to the current time.
+#[cfg(unix)]
+fn touch(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
 
-    fn drain(mut self, senders: Arc<RwLock<HashMap<Address, usize>>>) -> Result<TransactionsIterator> {
-        trace!("Draining transactions from: {}", self.path.display());
-        Ok(TransactionsIterator::new(&mut self.file, IteratorMode::Drain(senders, vec![self.path]))?)
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    match unsafe { ::libc::utimes(path.as_ptr(), ::std::ptr::null()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
     }
 }
 
-/// Iteration mode
-pub enum IteratorMode {
-    /// Remove the files after iterator is drained and remove senders from the set.
-    Drain(Arc<RwLock<HashMap<Address, usize>>>, Vec<PathBuf>),
-    /// Only read transactions.
-    ReadOnly,
+#[cfg(not(unix))]
+fn touch(path: &Path) -> io::Result<()> {
+    // Re-writing the file updates its modification time.
+    let mut content = Vec::new();
+    fs::File::open(path)?.read_to_end(&mut content)?;
+    fs::File::create(path)?.write_all(&content)
+}
+
+/// Syncs the directory containing given path, so that renames are persisted.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
 }
 
 /// Transactions iterator
 pub struct TransactionsIterator {
     content: io::Cursor<Vec<u8>>,
     position_before_tx: u64,
-    mode: IteratorMode,
 }
 
 impl TransactionsIterator {
-    /// Creates new transaction iterator by draining given file.
-    pub fn new(content: &mut fs::File, mode: IteratorMode) -> io::Result<Self> {
+    /// Creates new transaction iterator reading given file.
+    pub fn new(content: &mut fs::File) -> io::Result<Self> {
         content.seek(io::SeekFrom::Start(0))?;
 
         let mut bytes = Vec::new();
@@ -270,19 +952,12 @@ impl TransactionsIterator {
         Ok(TransactionsIterator {
             content: io::Cursor::new(bytes),
             position_before_tx: 0,
-            mode,
         })
     }
 
-    /// Join two iterators together.
-    /// Both files will be removed if both iterators are in `Drain` mode.
-    pub fn append(&mut self, other: Self) {
-        if let IteratorMode::Drain(_, ref mut paths) = self.mode {
-            if let IteratorMode::Drain(_, other_paths) = other.mode {
-                paths.extend_from_slice(&other_paths);
-            }
-        }
-        self.content.get_mut().extend_from_slice(&other.content.into_inner());
+    /// Returns number of bytes that were not read (after the iterator is exhausted).
+    fn unread(&self) -> usize {
+        self.content.get_ref().len() - self.position_before_tx as usize
     }
 }
 
@@ -293,37 +968,30 @@ impl Iterator for TransactionsIterator {
         let read_transaction = |content: &mut io::Cursor<_>| -> io::Result<_> {
             let mut sender = [0u8; 20];
             let mut hash = [0u8; 32];
-            let rlp_len = content.read_u32::<LittleEndian>()? as usize;
+            let len = content.read_u32::<LittleEndian>()?;
+            let rlp_len = (len & !API_KEY_FLAG) as usize;
             let mut rlp = Vec::with_capacity(rlp_len);
             rlp.resize(rlp_len, 0);
             content.read_exact(&mut sender)?;
             content.read_exact(&mut hash)?;
             content.read_exact(&mut rlp)?;
-            Ok(Transaction::new(sender.into(), hash.into(), rlp))
+            let transaction = Transaction::new(sender.into(), hash.into(), rlp);
+            if len & API_KEY_FLAG == 0 {
+                return Ok(transaction);
+            }
+
+            let mut api_key = vec![0u8; content.read_u8()? as usize];
+            content.read_exact(&mut api_key)?;
+            let api_key = String::from_utf8(api_key)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(transaction.with_api_key(Some(ApiKeys::normalize_id(api_key))))
         };
 
         self.position_before_tx = self.content.position();
         match read_transaction(&mut self.content) {
-            Ok(transaction) => {
-                if let IteratorMode::Drain(ref senders, _) = self.mode {
-                    Senders::decrement(&mut senders.write(), transaction.sender());
-                }
-                Some(transaction)
-            },
+            Ok(transaction) => Some(transaction),
             // Cursor is drained
-            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
-                if let IteratorMode::Drain(_, ref paths) = self.mode {
-                    for path in paths {
-                        let mut new = path.clone();
-                        new.set_extension("old");
-
-                        if let Err(err) = fs::rename(&path, new) {
-                            warn!("Unable to rename processed file at {}: {:?}", path.display(), err);
-                        }
-                    }
-                }
-                return None;
-            },
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
             Err(err) => {
                 // TODO [ToDr] Can we recover from that?
                 warn!("Error reading transaction from db: {:?}", err);
@@ -333,11 +1001,37 @@ impl Iterator for TransactionsIterator {
     }
 }
 
-struct Senders;
+/// Number of stored transactions per sender and per API key and targets of the stored transactions.
+#[derive(Debug, Default)]
+pub struct Counters {
+    senders: HashMap<Address, usize>,
+    api_keys: HashMap<String, usize>,
+    scheduled: HashMap<(BlockNumber, H256), usize>,
+}
 
-impl Senders {
-    pub fn increment(senders: &mut HashMap<Address, usize>, sender: &Address) {
-        match senders.entry(*sender) {
+impl Counters {
+    fn add(&mut self, block_number: BlockNumber, transaction: &Transaction) {
+        Self::increment(&mut self.senders, *transaction.sender());
+        if let Some(api_key) = transaction.api_key() {
+            Self::increment(&mut self.api_keys, api_key.to_owned());
+        }
+        Self::increment(&mut self.scheduled, (block_number, *transaction.hash()));
+    }
+
+    fn remove(&mut self, block_number: BlockNumber, transaction: &Transaction) {
+        Self::decrement(&mut self.senders, *transaction.sender());
+        if let Some(api_key) = transaction.api_key() {
+            Self::decrement(&mut self.api_keys, api_key.to_owned());
+        }
+        Self::decrement(&mut self.scheduled, (block_number, *transaction.hash()));
+    }
+
+    fn is_scheduled(&self, block_number: BlockNumber, hash: &H256) -> bool {
+        self.scheduled.contains_key(&(block_number, *hash))
+    }
+
+    fn increment<K: Hash + Eq>(map: &mut HashMap<K, usize>, key: K) {
+        match map.entry(key) {
             hash_map::Entry::Vacant(entry) => {
                 entry.insert(1);
             },
@@ -347,8 +1041,8 @@ impl Senders {
         }
     }
 
-    pub fn decrement(senders: &mut HashMap<Address, usize>, sender: &Address) {
-        if let hash_map::Entry::Occupied(mut entry) = senders.entry(*sender) {
+    fn decrement<K: Hash + Eq>(map: &mut HashMap<K, usize>, key: K) {
+        if let hash_map::Entry::Occupied(mut entry) = map.entry(key) {
             if entry.get() > &1 {
                 *entry.get_mut() -= 1;
             } else {
@@ -388,11 +1082,9 @@ mod tests {
         db.insert(tx(1)).unwrap();
         db.insert(tx(2)).unwrap();
 
-        let mut iter = db.drain(Default::default()).unwrap();
-        assert_eq!(iter.next(), Some(tx(0)));
-        assert_eq!(iter.next(), Some(tx(1)));
-        assert_eq!(iter.next(), Some(tx(2)));
-        assert_eq!(iter.next(), None);
+        let (_, transactions) = db.claim().unwrap();
+        assert_eq!(transactions, vec![tx(0), tx(1), tx(2)]);
+        assert!(dir.path().join("test.claimed").exists());
     }
 
     #[test]
@@ -412,7 +1104,7 @@ mod tests {
         assert_eq!(db.has(&5), true);
         assert_eq!(db.has(&6), true);
 
-        let mut iter = db.drain(5).unwrap().unwrap();
+        let mut iter = db.drain(5).unwrap().unwrap().map(Claimed::into_transaction);
         assert_eq!(iter.next(), Some(tx(1)));
         assert_eq!(iter.next(), Some(tx(2)));
         assert_eq!(iter.next(), Some(tx(0)));
@@ -430,7 +1122,7 @@ mod tests {
         }
 
         let db = Database::open(dir.path(), 1).unwrap();
-        let mut iter = db.drain(5).unwrap().unwrap();
+        let mut iter = db.drain(5).unwrap().unwrap().map(Claimed::into_transaction);
         assert_eq!(iter.next(), Some(tx(1)));
         assert_eq!(iter.next(), Some(tx(2)));
         assert_eq!(iter.next(), Some(tx(0)));
@@ -438,19 +1130,189 @@ mod tests {
     }
 
     #[test]
-    fn should_remove_transaction() {
+    fn should_remove_all_transactions_of_sender() {
+        let dir = TempDir::new("db1").unwrap();
+        {
+            let db = Database::open(dir.path(), 2).unwrap();
+            db.insert(5, tx(0)).unwrap();
+            db.insert(3, tx(1)).unwrap();
+            db.insert(3, tx(0)).unwrap();
+            assert_eq!(db.remove_sender(tx(0).sender()).unwrap(), vec![tx(0), tx(0)]);
+            assert!(db.sender_allowed(tx(0).sender()));
+        }
+
+        let db = Database::open(dir.path(), 2).unwrap();
+        let mut iter = db.drain(5).unwrap().unwrap().map(Claimed::into_transaction);
+        assert_eq!(iter.next(), Some(tx(1)));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn should_restore_api_keys_from_disk() {
+        let dir = TempDir::new("db1").unwrap();
+        let id = ApiKeys::key_id("key1");
+        let with_key = |tx: Transaction, key: &str| tx.with_api_key(Some(key.into()));
+        {
+            let db = Database::open(dir.path(), 1).unwrap();
+            db.insert(5, with_key(tx(0), &id)).unwrap();
+            db.insert(3, tx(1)).unwrap();
+            // Stored by previous versions.
+            db.insert(3, with_key(tx(2), "key1")).unwrap();
+            assert_eq!(db.api_key_count(&id), 1);
+        }
+
+        let db = Database::open(dir.path(), 1).unwrap();
+        assert_eq!(db.api_key_count(&id), 2);
+        let mut iter = db.drain(5).unwrap().unwrap().map(Claimed::into_transaction);
+        assert_eq!(iter.next(), Some(tx(1)));
+        assert_eq!(iter.next(), Some(with_key(tx(2), &id)));
+        assert_eq!(iter.next(), Some(with_key(tx(0), &id)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(db.api_key_count(&id), 0);
+    }
+
+    #[test]
+    fn should_restore_unacknowledged_transactions() {
         let dir = TempDir::new("db1").unwrap();
         {
             let db = Database::open(dir.path(), 1).unwrap();
             db.insert(5, tx(0)).unwrap();
             db.insert(3, tx(1)).unwrap();
             db.insert(3, tx(2)).unwrap();
-            db.remove(&3, tx(1).hash()).unwrap();
+
+            let mut iter = db.drain(5).unwrap().unwrap();
+            iter.next().unwrap().acknowledge();
+            iter.next().unwrap();
+            iter.next().unwrap().acknowledge();
+            assert!(dir.path().join("3.acks").exists());
+            assert!(dir.path().join("5.done").exists());
         }
 
         let db = Database::open(dir.path(), 1).unwrap();
+        assert!(!db.sender_allowed(tx(2).sender()));
+        assert_eq!(db.status(3, tx(1).hash()).unwrap(), Status::Submitted);
+        assert_eq!(db.status(3, tx(2).hash()).unwrap(), Status::Scheduled);
+        assert_eq!(db.status(3, tx(3).hash()).unwrap(), Status::Unknown);
         let mut iter = db.drain(5).unwrap().unwrap();
+        let restored = iter.next().unwrap();
+        assert_eq!(restored.transaction(), &tx(2));
+        assert_eq!(iter.next().map(Claimed::into_transaction), None);
+
+        restored.acknowledge();
+        assert!(dir.path().join("3.done").exists());
+        assert!(!dir.path().join("3.claimed").exists());
+        assert!(!dir.path().join("3.acks").exists());
+        assert_eq!(db.collect_garbage(Duration::from_secs(3600)).unwrap(), 0);
+    }
+
+    #[test]
+    fn should_repeat_interrupted_restore() {
+        let dir = TempDir::new("db1").unwrap();
+        {
+            let db = Database::open(dir.path(), 1).unwrap();
+            db.insert(3, tx(1)).unwrap();
+            db.insert(3, tx(2)).unwrap();
+
+            let mut iter = db.drain(3).unwrap().unwrap();
+            iter.next().unwrap().acknowledge();
+            iter.next().unwrap();
+        }
+        let claimed = dir.path().join("3.claimed");
+        let acks = dir.path().join("3.acks");
+        fs::copy(&claimed, dir.path().join("claimed.bak")).unwrap();
+        fs::copy(&acks, dir.path().join("acks.bak")).unwrap();
+        {
+            let db = Database::open(dir.path(), 1).unwrap();
+            db.insert(3, tx(3)).unwrap();
+        }
+
+        // Process died before the claimed file was marked as done.
+        fs::rename(dir.path().join("claimed.bak"), &claimed).unwrap();
+        fs::rename(dir.path().join("acks.bak"), &acks).unwrap();
+        let db = Database::open(dir.path(), 1).unwrap();
+        assert!(!claimed.exists());
+        assert_eq!(db.transactions().unwrap(), vec![(3, tx(2)), (3, tx(3))]);
+    }
+
+    #[test]
+    fn should_lock_database_directory() {
+        let dir = TempDir::new("db1").unwrap();
+        {
+            let db = Database::open(dir.path(), 1).unwrap();
+            db.insert(3, tx(1)).unwrap();
+            db.drain(3).unwrap().unwrap().next().unwrap();
+            db.insert(5, tx(0)).unwrap();
+
+            match Database::open_read_only(dir.path()) {
+                Err(Error(ErrorKind::Locked(_), _)) => {},
+                other => panic!("Expected locked database, got: {:?}", other),
+            }
+        }
+
+        // Claimed transaction is neither restored nor listed.
+        let db = Database::open_read_only(dir.path()).unwrap();
+        assert_eq!(db.transactions().unwrap(), vec![(5, tx(0))]);
+        assert!(dir.path().join("3.claimed").exists());
+        assert!(db.insert(5, tx(2)).is_err());
+        assert!(Database::open_read_only(dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn should_verify_and_compact_database() {
+        let dir = TempDir::new("db1").unwrap();
+        let db = Database::open(dir.path(), 2).unwrap();
+        db.insert(5, tx(0)).unwrap();
+        db.insert(3, tx(1)).unwrap();
+        db.insert(3, Transaction::new(*tx(2).sender(), *tx(3).hash(), tx(2).rlp().to_vec())).unwrap();
+        fs::OpenOptions::new().append(true).open(dir.path().join("5.txs")).unwrap()
+            .write_all(&[1, 2, 3]).unwrap();
+
+        let issues = vec![
+            Issue::Mismatch { target: 3, hash: *tx(3).hash() },
+            Issue::Corrupted { target: 5, bytes: 3 },
+        ];
+        assert_eq!(db.verify().unwrap(), issues);
+        assert_eq!(db.compact().unwrap(), issues);
+        assert_eq!(db.verify().unwrap(), vec![]);
+        assert_eq!(db.stats().transactions, 2);
+        assert_eq!(db.transactions().unwrap(), vec![(3, tx(1)), (5, tx(0))]);
+    }
+
+    #[test]
+    fn should_return_stats() {
+        let dir = TempDir::new("db1").unwrap();
+        let db = Database::open(dir.path(), 2).unwrap();
+        db.insert(5, tx(0)).unwrap();
+        db.insert(3, tx(1)).unwrap();
+        db.insert(3, tx(2)).unwrap();
+
+        assert_eq!(db.stats(), Stats {
+            transactions: 3,
+            senders: 3,
+            targets: 2,
+            earliest: Some(3),
+            latest: Some(5),
+        });
+    }
+
+    #[test]
+    fn should_remove_transaction() {
+        let dir = TempDir::new("db1").unwrap();
+        {
+            let db = Database::open(dir.path(), 4).unwrap();
+            db.insert(5, tx(0)).unwrap();
+            db.insert(3, tx(1)).unwrap();
+            db.insert(3, tx(2)).unwrap();
+            db.remove(&3, tx(1).hash()).unwrap();
+            // Records are appended to the rewritten file.
+            db.insert(3, tx(3)).unwrap();
+        }
+        assert!(!dir.path().join("3.txs.tmp").exists());
+
+        let db = Database::open(dir.path(), 4).unwrap();
+        let mut iter = db.drain(5).unwrap().unwrap().map(Claimed::into_transaction);
         assert_eq!(iter.next(), Some(tx(2)));
+        assert_eq!(iter.next(), Some(tx(3)));
         assert_eq!(iter.next(), Some(tx(0)));
         assert_eq!(iter.next(), None);
     }
@@ -1,37 +1,386 @@
-use jsonrpc_core::{Error, ErrorCode, Value};
+//! JSON-RPC errors.
+//!
+//! Every rejection has a stable error `code` and a structured `data` object
+//! containing a machine-readable `reason` and reason-specific fields.
+//! Quantities (gas, gas price, balance, nonce) are encoded as decimal strings,
+//! block numbers and timestamps (in seconds) as numbers.
+//! Timestamp errors additionally carry millisecond values (`maxMs`/`minMs` and `gotMs`).
 
-pub fn rlp<T: ::std::fmt::Display>(error: T) -> Error {
-	Error {
-		code: ErrorCode::InvalidParams,
-		message: "Invalid RLP.".into(),
-		data: Some(Value::String(format!("{}", error))),
-	}
+use std::fmt::Display;
+
+use jsonrpc_core::{Error, ErrorCode};
+use rustc_hex::ToHex;
+use serde_json;
+
+use submitter::Queue;
+use types::{Address, U256};
+
+/// Catalogue of error codes.
+pub mod codes {
+	/// Request is not authorized to call the method.
+	pub const UNAUTHORIZED: i64 = -32010;
+	/// Request exceeds limits of the API key.
+	pub const API_KEY_LIMIT: i64 = -32011;
+	/// Client or sender is sending too many requests.
+	pub const RATE_LIMITED: i64 = -32012;
+	/// Transaction RLP cannot be decoded.
+	pub const INVALID_RLP: i64 = -32020;
+	/// Transaction is malformed (invalid signature, chain id, etc).
+	pub const INVALID_TRANSACTION: i64 = -32021;
+	/// Transaction gas is below intrinsic gas.
+	pub const GAS_TOO_LOW: i64 = -32022;
+	/// Transaction gas is above the limit.
+	pub const GAS_TOO_HIGH: i64 = -32023;
+	/// Transaction gas price is below the minimum.
+	pub const GAS_PRICE_TOO_LOW: i64 = -32024;
+	/// Transaction recipient, method or contract creation is not allowed by the rules.
+	pub const TRANSACTION_NOT_ALLOWED: i64 = -32025;
+	/// Transaction value is above the limit.
+	pub const VALUE_TOO_HIGH: i64 = -32026;
+	/// Sender is not certified.
+	pub const SENDER_NOT_CERTIFIED: i64 = -32030;
+	/// Sender balance can't cover the transaction cost.
+	pub const INSUFFICIENT_BALANCE: i64 = -32031;
+	/// Transaction nonce doesn't match sender's nonce.
+	pub const INVALID_NONCE: i64 = -32032;
+	/// Sender already has too many transactions scheduled.
+	pub const TOO_MANY_TRANSACTIONS: i64 = -32033;
+	/// Requested block number is not accepted.
+	pub const INVALID_BLOCK: i64 = -32040;
+	/// Requested timestamp is not accepted.
+	pub const INVALID_TIMESTAMP: i64 = -32041;
+	/// Transaction id or address is malformed.
+	pub const INVALID_ID: i64 = -32050;
+	/// Scheduled transaction was not found.
+	pub const NOT_FOUND: i64 = -32051;
+	/// Blockchain node could not be queried.
+	pub const NODE_ERROR: i64 = -32060;
+	/// Transaction chain is not served by the scheduler or cannot be determined.
+	pub const UNSUPPORTED_CHAIN: i64 = -32070;
 }
-pub fn transaction<T: ::std::fmt::Display>(error: T) -> Error {
-	Error {
-		code: ErrorCode::InvalidParams,
-		message: "Invalid Transaction.".into(),
-		data: Some(Value::String(format!("{}", error))),
-	}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "camelCase")]
+enum Reason {
+	Unauthorized,
+	ApiKeyRequired,
+	UnknownApiKey,
+	QueueNotAllowed { queue: String },
+	ApiKeyQuotaExceeded { max: usize },
+	RateLimited { scope: &'static str },
+	ApiKeyRateLimited {
+		#[serde(rename = "perMinute")]
+		per_minute: u32,
+	},
+	InvalidRlp { details: String },
+	InvalidTransaction { details: String },
+	GasTooLow { required: String, got: String },
+	GasTooHigh { max: String, got: String },
+	GasPriceTooLow { required: String, got: String },
+	RecipientNotAllowed { to: String },
+	ContractCreationNotAllowed,
+	MethodNotAllowed { to: String, selector: Option<String> },
+	ValueTooHigh { max: String, got: String },
+	SenderNotCertified,
+	InsufficientBalance { required: String, got: String },
+	InvalidNonce { required: String, got: String, strict: bool },
+	TooManyTransactions,
+	BlockTooHigh { max: u64, got: u64 },
+	TimestampTooHigh {
+		max: u64,
+		got: u64,
+		#[serde(rename = "maxMs")]
+		max_ms: u64,
+		#[serde(rename = "gotMs")]
+		got_ms: u64,
+	},
+	BlockTooLow { min: u64, got: u64 },
+	TimestampTooLow {
+		min: u64,
+		got: u64,
+		#[serde(rename = "minMs")]
+		min_ms: u64,
+		#[serde(rename = "gotMs")]
+		got_ms: u64,
+	},
+	InvalidCondition,
+	InvalidId,
+	InvalidAddress,
+	NotFound,
+	NodeError { details: String },
+	UnsupportedChain {
+		#[serde(rename = "chainId")]
+		chain_id: u64,
+	},
+	ChainRequired,
+	InvalidChainId,
+	Internal { details: String },
 }
-pub fn block<T: ::std::fmt::Display>(error: T) -> Error {
+
+fn build<T: Into<String>>(code: ErrorCode, message: T, reason: Reason) -> Error {
 	Error {
-		code: ErrorCode::InvalidParams,
-		message: "Invalid block number.".into(),
-		data: Some(Value::String(format!("{}", error))),
+		code,
+		message: message.into(),
+		data: Some(serde_json::to_value(&reason).expect("Reason serialization is infallible; qed")),
 	}
 }
-pub fn timestamp<T: ::std::fmt::Display>(error: T) -> Error {
-	Error {
-		code: ErrorCode::InvalidParams,
-		message: "Invalid timestamp.".into(),
-		data: Some(Value::String(format!("{}", error))),
-	}
+
+fn custom<T: Into<String>>(code: i64, message: T, reason: Reason) -> Error {
+	build(ErrorCode::ServerError(code), message, reason)
 }
-pub fn internal<T: ::std::fmt::Display>(error: T) -> Error {
-	Error {
-		code: ErrorCode::InternalError,
-		message: "Internal Error".into(),
-		data: Some(Value::String(format!("{}", error))),
+
+pub fn unauthorized() -> Error {
+	custom(codes::UNAUTHORIZED, "Unauthorized.", Reason::Unauthorized)
+}
+pub fn api_key_required() -> Error {
+	custom(codes::UNAUTHORIZED, "API key required.", Reason::ApiKeyRequired)
+}
+pub fn unknown_api_key() -> Error {
+	custom(codes::UNAUTHORIZED, "Unknown API key.", Reason::UnknownApiKey)
+}
+pub fn queue_not_allowed(queue: Queue) -> Error {
+	let queue = match queue {
+		Queue::Block => "block",
+		Queue::Timestamp => "time",
+	}.to_owned();
+	custom(codes::API_KEY_LIMIT, format!("Scheduling to {} queue is not allowed.", queue), Reason::QueueNotAllowed { queue })
+}
+pub fn api_key_quota(max: usize) -> Error {
+	custom(codes::API_KEY_LIMIT, format!("Too many scheduled transactions. Maximal: {}", max), Reason::ApiKeyQuotaExceeded { max })
+}
+pub fn api_key_rate_limited(per_minute: u32) -> Error {
+	custom(codes::API_KEY_LIMIT, format!("Too many requests. Maximal: {} per minute", per_minute), Reason::ApiKeyRateLimited { per_minute })
+}
+pub fn ip_rate_limited() -> Error {
+	custom(codes::RATE_LIMITED, "Client exceeded rate limit.", Reason::RateLimited { scope: "ip" })
+}
+pub fn sender_rate_limited() -> Error {
+	custom(codes::RATE_LIMITED, "Sender exceeded rate limit.", Reason::RateLimited { scope: "sender" })
+}
+pub fn rlp<T: Display>(error: T) -> Error {
+	custom(codes::INVALID_RLP, "Invalid RLP.", Reason::InvalidRlp { details: error.to_string() })
+}
+pub fn transaction<T: Display>(error: T) -> Error {
+	custom(codes::INVALID_TRANSACTION, "Invalid Transaction.", Reason::InvalidTransaction { details: error.to_string() })
+}
+pub fn gas_too_low(required: U256, got: U256) -> Error {
+	custom(codes::GAS_TOO_LOW, format!("Gas is too low. Required: {}", required), Reason::GasTooLow {
+		required: required.to_string(),
+		got: got.to_string(),
+	})
+}
+pub fn gas_too_high(max: U256, got: U256) -> Error {
+	custom(codes::GAS_TOO_HIGH, format!("Gas is too high. Maximal: {}", max), Reason::GasTooHigh {
+		max: max.to_string(),
+		got: got.to_string(),
+	})
+}
+pub fn gas_price_too_low(required: U256, got: U256) -> Error {
+	custom(codes::GAS_PRICE_TOO_LOW, format!("Gas price is too low. Required: {} wei", required), Reason::GasPriceTooLow {
+		required: required.to_string(),
+		got: got.to_string(),
+	})
+}
+pub fn recipient_not_allowed(to: &Address) -> Error {
+	custom(codes::TRANSACTION_NOT_ALLOWED, "Transaction recipient is not allowed.", Reason::RecipientNotAllowed {
+		to: format!("0x{}", to.to_hex()),
+	})
+}
+pub fn contract_creation_not_allowed() -> Error {
+	custom(codes::TRANSACTION_NOT_ALLOWED, "Contract creation is not allowed.", Reason::ContractCreationNotAllowed)
+}
+pub fn method_not_allowed(to: &Address, selector: Option<&[u8]>) -> Error {
+	custom(codes::TRANSACTION_NOT_ALLOWED, "Called method is not allowed.", Reason::MethodNotAllowed {
+		to: format!("0x{}", to.to_hex()),
+		selector: selector.map(|selector| format!("0x{}", selector.to_hex())),
+	})
+}
+pub fn value_too_high(max: U256, got: U256) -> Error {
+	custom(codes::VALUE_TOO_HIGH, format!("Value is too high. Maximal: {} wei", max), Reason::ValueTooHigh {
+		max: max.to_string(),
+		got: got.to_string(),
+	})
+}
+pub fn not_certified() -> Error {
+	custom(codes::SENDER_NOT_CERTIFIED, "Sender is not certified.", Reason::SenderNotCertified)
+}
+pub fn insufficient_balance(required: U256, got: U256) -> Error {
+	custom(codes::INSUFFICIENT_BALANCE, format!("Insufficient balance (required: {}, got: {})", required, got), Reason::InsufficientBalance {
+		required: required.to_string(),
+		got: got.to_string(),
+	})
+}
+pub fn invalid_nonce(required: U256, got: U256, strict: bool) -> Error {
+	let message = if strict {
+		format!("Invalid nonce (required: {}, got: {})", required, got)
+	} else {
+		format!("Invalid nonce (required at least: {}, got: {})", required, got)
+	};
+	custom(codes::INVALID_NONCE, message, Reason::InvalidNonce {
+		required: required.to_string(),
+		got: got.to_string(),
+		strict,
+	})
+}
+pub fn too_many_transactions() -> Error {
+	custom(codes::TOO_MANY_TRANSACTIONS, "Sender already has too many transactions.", Reason::TooManyTransactions)
+}
+pub fn block_too_high(max: u64, got: u64) -> Error {
+	custom(codes::INVALID_BLOCK, format!("Block number is too high: {} > {}", got, max), Reason::BlockTooHigh { max, got })
+}
+/// Timestamps are given in milliseconds.
+pub fn timestamp_too_high(max_ms: u64, got_ms: u64) -> Error {
+	let (max, got) = (max_ms / 1000, got_ms / 1000);
+	custom(codes::INVALID_TIMESTAMP, format!("Timestamp is too high: {} > {}", got, max), Reason::TimestampTooHigh { max, got, max_ms, got_ms })
+}
+pub fn block_too_low(min: u64, got: u64) -> Error {
+	custom(codes::INVALID_BLOCK, format!("Block number is too low: {} < {}", got, min), Reason::BlockTooLow { min, got })
+}
+/// Timestamps are given in milliseconds.
+pub fn timestamp_too_low(min_ms: u64, got_ms: u64) -> Error {
+	let (min, got) = (min_ms / 1000, got_ms / 1000);
+	custom(codes::INVALID_TIMESTAMP, format!("Timestamp is too low: {} < {}", got, min), Reason::TimestampTooLow { min, got, min_ms, got_ms })
+}
+pub fn invalid_condition() -> Error {
+	custom(codes::INVALID_TIMESTAMP, "Timestamp is out of range.", Reason::InvalidCondition)
+}
+pub fn invalid_id() -> Error {
+	custom(codes::INVALID_ID, "Invalid id.", Reason::InvalidId)
+}
+pub fn invalid_address() -> Error {
+	custom(codes::INVALID_ID, "Invalid address.", Reason::InvalidAddress)
+}
+pub fn not_found() -> Error {
+	custom(codes::NOT_FOUND, "Not found.", Reason::NotFound)
+}
+pub fn node<T: ::std::fmt::Debug>(error: T) -> Error {
+	custom(codes::NODE_ERROR, "Unable to query blockchain node.", Reason::NodeError { details: format!("{:?}", error) })
+}
+pub fn unsupported_chain(chain_id: u64) -> Error {
+	custom(codes::UNSUPPORTED_CHAIN, format!("Unsupported chain: {}", chain_id), Reason::UnsupportedChain { chain_id })
+}
+pub fn chain_required() -> Error {
+	custom(
+		codes::UNSUPPORTED_CHAIN,
+		"Chain id is required: sign the transaction with replay protection (EIP-155) or pass the chain id explicitly.",
+		Reason::ChainRequired,
+	)
+}
+pub fn chain_not_selected() -> Error {
+	custom(codes::UNSUPPORTED_CHAIN, "Multiple chains are served, pass the chain id explicitly.", Reason::ChainRequired)
+}
+pub fn invalid_chain_id() -> Error {
+	custom(codes::UNSUPPORTED_CHAIN, "Invalid chain id: expected a number.", Reason::InvalidChainId)
+}
+pub fn internal<T: Display>(error: T) -> Error {
+	build(ErrorCode::InternalError, "Internal Error", Reason::Internal { details: error.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+	use jsonrpc_core::ErrorCode;
+	use serde_json::{self, Value};
+	use submitter::Queue;
+	use types::{Address, U256};
+	use super::*;
+
+	fn u(value: u64) -> U256 {
+		value.into()
+	}
+
+	fn assert_error(error: Error, code: i64, data: &str) {
+		assert_eq!(error.code, ErrorCode::ServerError(code));
+		assert_eq!(error.data, Some(serde_json::from_str::<Value>(data).unwrap()));
+	}
+
+	#[test]
+	fn should_have_distinct_codes() {
+		use super::codes::*;
+		let mut all = vec![
+			UNAUTHORIZED, API_KEY_LIMIT, RATE_LIMITED, INVALID_RLP, INVALID_TRANSACTION,
+			GAS_TOO_LOW, GAS_TOO_HIGH, GAS_PRICE_TOO_LOW, TRANSACTION_NOT_ALLOWED,
+			VALUE_TOO_HIGH, SENDER_NOT_CERTIFIED,
+			INSUFFICIENT_BALANCE, INVALID_NONCE, TOO_MANY_TRANSACTIONS, INVALID_BLOCK,
+			INVALID_TIMESTAMP, INVALID_ID, NOT_FOUND, NODE_ERROR, UNSUPPORTED_CHAIN,
+		];
+		let len = all.len();
+		all.sort();
+		all.dedup();
+		assert_eq!(all.len(), len);
+		// Implementation-defined server errors range.
+		assert!(all.iter().all(|code| *code <= -32000 && *code >= -32099));
+	}
+
+	#[test]
+	fn should_serialize_access_errors() {
+		assert_error(unauthorized(), codes::UNAUTHORIZED, r#"{"reason":"unauthorized"}"#);
+		assert_error(api_key_required(), codes::UNAUTHORIZED, r#"{"reason":"apiKeyRequired"}"#);
+		assert_error(unknown_api_key(), codes::UNAUTHORIZED, r#"{"reason":"unknownApiKey"}"#);
+		assert_error(queue_not_allowed(Queue::Timestamp), codes::API_KEY_LIMIT, r#"{"reason":"queueNotAllowed","queue":"time"}"#);
+		assert_error(api_key_quota(5), codes::API_KEY_LIMIT, r#"{"reason":"apiKeyQuotaExceeded","max":5}"#);
+		assert_error(api_key_rate_limited(60), codes::API_KEY_LIMIT, r#"{"reason":"apiKeyRateLimited","perMinute":60}"#);
+		assert_error(ip_rate_limited(), codes::RATE_LIMITED, r#"{"reason":"rateLimited","scope":"ip"}"#);
+		assert_error(sender_rate_limited(), codes::RATE_LIMITED, r#"{"reason":"rateLimited","scope":"sender"}"#);
+	}
+
+	#[test]
+	fn should_serialize_transaction_errors() {
+		assert_error(rlp("Bad"), codes::INVALID_RLP, r#"{"reason":"invalidRlp","details":"Bad"}"#);
+		assert_error(transaction("Bad"), codes::INVALID_TRANSACTION, r#"{"reason":"invalidTransaction","details":"Bad"}"#);
+		assert_error(gas_too_low(u(21_000), u(5)), codes::GAS_TOO_LOW, r#"{"reason":"gasTooLow","required":"21000","got":"5"}"#);
+		assert_error(gas_too_high(u(100), u(200)), codes::GAS_TOO_HIGH, r#"{"reason":"gasTooHigh","max":"100","got":"200"}"#);
+		assert_error(gas_price_too_low(u(20), u(1)), codes::GAS_PRICE_TOO_LOW, r#"{"reason":"gasPriceTooLow","required":"20","got":"1"}"#);
+		assert_error(
+			recipient_not_allowed(&Address::from(1)),
+			codes::TRANSACTION_NOT_ALLOWED,
+			r#"{"reason":"recipientNotAllowed","to":"0x0000000000000000000000000000000000000001"}"#,
+		);
+		assert_error(contract_creation_not_allowed(), codes::TRANSACTION_NOT_ALLOWED, r#"{"reason":"contractCreationNotAllowed"}"#);
+		assert_error(
+			method_not_allowed(&Address::from(1), Some(&[0xa9, 0x05, 0x9c, 0xbb])),
+			codes::TRANSACTION_NOT_ALLOWED,
+			r#"{"reason":"methodNotAllowed","to":"0x0000000000000000000000000000000000000001","selector":"0xa9059cbb"}"#,
+		);
+		assert_error(value_too_high(u(1), u(2)), codes::VALUE_TOO_HIGH, r#"{"reason":"valueTooHigh","max":"1","got":"2"}"#);
+	}
+
+	#[test]
+	fn should_serialize_sender_errors() {
+		assert_error(not_certified(), codes::SENDER_NOT_CERTIFIED, r#"{"reason":"senderNotCertified"}"#);
+		assert_error(insufficient_balance(u(10), u(1)), codes::INSUFFICIENT_BALANCE, r#"{"reason":"insufficientBalance","required":"10","got":"1"}"#);
+		assert_error(invalid_nonce(u(3), u(1), true), codes::INVALID_NONCE, r#"{"reason":"invalidNonce","required":"3","got":"1","strict":true}"#);
+		assert_error(too_many_transactions(), codes::TOO_MANY_TRANSACTIONS, r#"{"reason":"tooManyTransactions"}"#);
+		assert_error(node("Timeout"), codes::NODE_ERROR, r#"{"reason":"nodeError","details":"\"Timeout\""}"#);
+	}
+
+	#[test]
+	fn should_serialize_target_and_id_errors() {
+		assert_error(block_too_high(10, 11), codes::INVALID_BLOCK, r#"{"reason":"blockTooHigh","max":10,"got":11}"#);
+		assert_error(
+			timestamp_too_high(10_000, 11_500),
+			codes::INVALID_TIMESTAMP,
+			r#"{"reason":"timestampTooHigh","max":10,"got":11,"maxMs":10000,"gotMs":11500}"#,
+		);
+		assert_error(block_too_low(10, 9), codes::INVALID_BLOCK, r#"{"reason":"blockTooLow","min":10,"got":9}"#);
+		assert_error(
+			timestamp_too_low(10_000, 9_500),
+			codes::INVALID_TIMESTAMP,
+			r#"{"reason":"timestampTooLow","min":10,"got":9,"minMs":10000,"gotMs":9500}"#,
+		);
+		assert_error(invalid_condition(), codes::INVALID_TIMESTAMP, r#"{"reason":"invalidCondition"}"#);
+		assert_error(invalid_id(), codes::INVALID_ID, r#"{"reason":"invalidId"}"#);
+		assert_error(invalid_address(), codes::INVALID_ID, r#"{"reason":"invalidAddress"}"#);
+		assert_error(not_found(), codes::NOT_FOUND, r#"{"reason":"notFound"}"#);
+		assert_error(unsupported_chain(42), codes::UNSUPPORTED_CHAIN, r#"{"reason":"unsupportedChain","chainId":42}"#);
+		assert_error(chain_required(), codes::UNSUPPORTED_CHAIN, r#"{"reason":"chainRequired"}"#);
+		assert_error(chain_not_selected(), codes::UNSUPPORTED_CHAIN, r#"{"reason":"chainRequired"}"#);
+		assert_error(invalid_chain_id(), codes::UNSUPPORTED_CHAIN, r#"{"reason":"invalidChainId"}"#);
+	}
+
+	#[test]
+	fn should_serialize_internal_error() {
+		let error = internal("Disk full");
+		assert_eq!(error.code, ErrorCode::InternalError);
+		assert_eq!(error.data, Some(serde_json::from_str::<Value>(r#"{"reason":"internal","details":"Disk full"}"#).unwrap()));
 	}
 }
@@ -0,0 +1,146 @@
+//! Minimal gas price policies.
+
+use std::cmp;
+use std::sync::Arc;
+
+use ethcore::transaction::UnverifiedTransaction;
+use futures::{future, Future};
+use parking_lot::RwLock;
+use rlp::UntrustedRlp;
+use web3;
+
+use blockchain::Blockchain;
+use options::SharedOptions;
+use types::{BlockNumber, Transaction, U256};
+
+/// Policy of computing minimal gas price.
+///
+/// Dynamic policies never go below the static `min_gas_price`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum GasPricePolicy {
+    /// Static minimal gas price.
+    Static,
+    /// Percentile of gas prices of transactions included in recent blocks.
+    Percentile {
+        /// Percentile (0-100).
+        percentile: u8,
+        /// Number of recent blocks to consider.
+        blocks: u64,
+    },
+    /// Multiple of the gas price suggested by the node (`eth_gasPrice`).
+    Multiple {
+        /// Multiplier in percents.
+        percent: u64,
+    },
+}
+
+impl Default for GasPricePolicy {
+    fn default() -> Self {
+        GasPricePolicy::Static
+    }
+}
+
+/// Computes minimal gas price according to the policy.
+///
+/// The policy and the static minimal gas price are read from `Options` (see `Options::gas_price`).
+/// Dynamic prices are computed at most once per block.
+#[derive(Debug)]
+pub struct GasPriceOracle {
+    blockchain: Arc<Blockchain>,
+    options: Arc<SharedOptions>,
+    cached: Arc<RwLock<Option<(Settings, U256)>>>,
+}
+
+/// Block number, policy and static minimal gas price a cached price was computed with.
+type Settings = (BlockNumber, GasPricePolicy, U256);
+
+impl GasPriceOracle {
+    /// Creates new oracle.
+    pub fn new(blockchain: Arc<Blockchain>, options: Arc<SharedOptions>) -> Self {
+        GasPriceOracle {
+            blockchain,
+            options,
+            cached: Default::default(),
+        }
+    }
+
+    /// Returns current minimal gas price.
+    pub fn minimum(&self) -> Box<Future<Item=U256, Error=web3::Error> + Send> {
+        let latest_block = self.blockchain.latest_block();
+        let (policy, floor) = {
+            let options = self.options.get();
+            (options.gas_price, U256::from(options.min_gas_price))
+        };
+        let settings = (latest_block, policy, floor);
+        if let Some((cached, price)) = *self.cached.read() {
+            if cached == settings {
+                return Box::new(future::ok(price));
+            }
+        }
+
+        let price: Box<Future<Item=U256, Error=web3::Error> + Send> = match policy {
+            GasPricePolicy::Static => return Box::new(future::ok(floor)),
+            GasPricePolicy::Multiple { percent } => Box::new(self.blockchain.gas_price()
+                .map(move |price| price * U256::from(percent) / U256::from(100))),
+            GasPricePolicy::Percentile { percentile: p, blocks } => {
+                let first = latest_block.saturating_sub(blocks.saturating_sub(1));
+                let prices = (first..latest_block + 1).map(|block| self.blockchain.block_gas_prices(block)).collect::<Vec<_>>();
+                Box::new(future::join_all(prices).map(move |prices| {
+                    let mut prices = prices.into_iter().flat_map(|prices| prices).collect::<Vec<_>>();
+                    percentile(&mut prices, p).unwrap_or(floor)
+                }))
+            },
+        };
+
+        let cached = self.cached.clone();
+        Box::new(price.map(move |price| {
+            let price = cmp::max(price, floor);
+            debug!("Minimal gas price at block {}: {}", latest_block, price);
+            *cached.write() = Some((settings, price));
+            price
+        }))
+    }
+}
+
+/// Returns false if the transaction is priced below given minimal gas price.
+///
+/// Transactions that can't be decoded are considered priced correctly.
+pub fn is_priced(transaction: &Transaction, minimum: U256) -> bool {
+    match UntrustedRlp::new(transaction.rlp()).as_val::<UnverifiedTransaction>() {
+        Ok(tx) => tx.gas_price >= minimum,
+        Err(_) => true,
+    }
+}
+
+/// Returns given percentile of the prices.
+fn percentile(prices: &mut [U256], percentile: u8) -> Option<U256> {
+    if prices.is_empty() {
+        return None;
+    }
+
+    prices.sort();
+    let index = (prices.len() - 1) * cmp::min(percentile, 100) as usize / 100;
+    Some(prices[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use types::U256;
+    use super::percentile;
+
+    fn u(value: u64) -> U256 {
+        value.into()
+    }
+
+    #[test]
+    fn should_compute_percentile() {
+        let mut prices = (1..11).rev().map(u).collect::<Vec<_>>();
+
+        assert_eq!(percentile(&mut [], 50), None);
+        assert_eq!(percentile(&mut prices, 0), Some(u(1)));
+        assert_eq!(percentile(&mut prices, 50), Some(u(5)));
+        assert_eq!(percentile(&mut prices, 100), Some(u(10)));
+        assert_eq!(percentile(&mut prices, 200), Some(u(10)));
+    }
+}
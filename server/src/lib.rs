@@ -10,22 +10,35 @@ extern crate error_chain;
 #[macro_use]
 extern crate futures;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 extern crate byteorder;
+extern crate ethabi;
 extern crate ethcore;
 extern crate ethcore_bigint;
+extern crate ethkey;
+extern crate ethstore;
 extern crate futures_cpupool;
+extern crate hyper;
+extern crate hyper_tls;
 extern crate jsonrpc_core;
 extern crate jsonrpc_http_server;
+extern crate jsonrpc_ipc_server;
+extern crate libc;
+extern crate lru_cache;
 extern crate parking_lot;
 extern crate rlp;
 extern crate rustc_hex;
 extern crate serde;
-extern crate serde_json;
 extern crate time;
+extern crate tiny_keccak;
+extern crate tokio_timer;
 #[cfg(feature = "ui")]
 extern crate txsched_ui;
 extern crate web3;
@@ -36,13 +49,26 @@ extern crate tempdir;
 extern crate env_logger;
 
 pub mod blockchain;
+pub mod chain;
+pub mod client;
 pub mod database;
+pub mod gas_price;
+pub mod policy;
+pub mod rules;
+pub mod schedule;
 pub mod server;
+pub mod sign;
 pub mod submitter;
+pub mod transport;
+pub mod types;
 
+mod admin;
+mod cache;
 mod errors;
+mod middleware;
+mod notify;
 mod options;
-mod types;
+mod rate_limit;
 #[cfg(feature = "ui")]
 mod ui;
 #[cfg(not(feature = "ui"))]
@@ -50,13 +76,32 @@ mod ui { pub use super::ui_noop::*; }
 mod ui_noop;
 mod verifier;
 
-pub use options::Options;
+pub use options::{Options, ApiKeys, ApiKey, PastTargets, SharedOptions};
+pub use rate_limit::Limit;
+pub use verifier::verify_transaction;
 
 /// Type of the transport to instantiate.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransportType {
     /// IPC (local) transport
     Ipc(String),
-    /// HTTP transport (can be remote)
+    /// HTTP(S) transport (can be remote)
     Http(String),
+    /// WebSocket transport (can be remote)
+    Ws(String),
+}
+
+impl TransportType {
+    /// Selects transport by URL scheme: `http(s)://`, `ws://`, `wss://` or `ipc:///path/to/socket`.
+    pub fn from_url(url: &str) -> Result<Self, String> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Ok(TransportType::Http(url.into()))
+        } else if url.starts_with("ws://") || url.starts_with("wss://") {
+            Ok(TransportType::Ws(url.into()))
+        } else if url.starts_with("ipc://") && url.len() > "ipc://".len() {
+            Ok(TransportType::Ipc(url["ipc://".len()..].into()))
+        } else {
+            Err(format!("Unsupported node URL {} (expected http://, https://, ws://, wss:// or ipc:///path).", url))
+        }
+    }
 }
@@ -0,0 +1,189 @@
+//! HTTP request middleware: API keys verification and UI.
+
+use std::net::IpAddr;
+use std::str;
+use std::sync::Arc;
+
+use jsonrpc_core::futures::future;
+use jsonrpc_core::{Error, Failure, Id, Output, Version};
+use jsonrpc_http_server::{self as http, hyper, RequestMiddleware};
+use serde_json;
+
+use errors;
+use options::ApiKeys;
+use rate_limit::{Limit, RateLimiter};
+
+/// Name of the header carrying the API key.
+const API_KEY_HEADER: &str = "X-Api-Key";
+/// Name of the query parameter carrying the API key.
+const API_KEY_PARAM: &str = "apiKey";
+
+/// Extracts API key from request header or query parameters.
+pub fn api_key(request: &hyper::Request) -> Option<String> {
+    let header = request.headers().get_raw(API_KEY_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|value| str::from_utf8(value).ok());
+    if let Some(key) = header {
+        return Some(key.to_owned());
+    }
+
+    request.query().and_then(|query| query
+        .split('&')
+        .filter_map(|pair| {
+            let mut it = pair.splitn(2, '=');
+            match (it.next(), it.next()) {
+                (Some(API_KEY_PARAM), Some(value)) => percent_decode(value),
+                _ => None,
+            }
+        })
+        .next()
+    )
+}
+
+/// Decodes `application/x-www-form-urlencoded` value.
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut it = value.bytes();
+    while let Some(byte) = it.next() {
+        match byte {
+            b'%' => {
+                let digit = |byte: Option<u8>| byte.and_then(|byte| (byte as char).to_digit(16));
+                match (digit(it.next()), digit(it.next())) {
+                    (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+                    _ => return None,
+                }
+            },
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Returns client IP address.
+///
+/// If the server runs behind `trusted_proxies` proxies, the address appended to `X-Forwarded-For` header
+/// by the outermost trusted proxy is used (entries added before can be forged by the client).
+pub fn client_ip(request: &hyper::Request, trusted_proxies: usize) -> Option<IpAddr> {
+    if trusted_proxies > 0 {
+        let forwarded = request.headers().get_raw("X-Forwarded-For")
+            .map(|raw| forwarded_for(raw.iter(), trusted_proxies));
+        if let Some(Some(ip)) = forwarded {
+            return Some(ip);
+        }
+    }
+
+    request.remote_addr().map(|addr| addr.ip())
+}
+
+/// Returns `trusted_proxies`-th address from the right of `X-Forwarded-For` header values
+/// (or the left-most one if there are less addresses).
+fn forwarded_for<'a, I: Iterator<Item=&'a [u8]>>(values: I, trusted_proxies: usize) -> Option<IpAddr> {
+    let addresses = values
+        .filter_map(|value| str::from_utf8(value).ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .collect::<Vec<_>>();
+    let index = addresses.len().checked_sub(trusted_proxies).unwrap_or(0);
+    addresses.get(index).and_then(|ip| ip.parse().ok())
+}
+
+/// Request middleware verifying API keys of JSON-RPC requests
+/// and serving UI files.
+pub struct Middleware {
+    ui: ::ui::Ui,
+    api_keys: Arc<ApiKeys>,
+    limiter: RateLimiter<String>,
+}
+
+impl Middleware {
+    /// Creates new middleware.
+    pub fn new(api_keys: Arc<ApiKeys>) -> Self {
+        Middleware {
+            ui: Default::default(),
+            api_keys,
+            limiter: Default::default(),
+        }
+    }
+
+    fn verify(&self, request: &hyper::Request) -> Result<(), (hyper::StatusCode, Error)> {
+        let key = match api_key(request) {
+            Some(key) => key,
+            None if self.api_keys.required => {
+                return Err((hyper::StatusCode::Unauthorized, errors::api_key_required()));
+            },
+            None => return Ok(()),
+        };
+
+        let limits = match self.api_keys.keys.get(&key) {
+            Some(limits) => limits,
+            None => {
+                debug!("Rejecting request with unknown API key.");
+                return Err((hyper::StatusCode::Unauthorized, errors::unknown_api_key()));
+            },
+        };
+
+        if let Some(per_minute) = limits.requests_per_minute {
+            if !self.limiter.check(key, Limit::per_minute(per_minute)) {
+                debug!("Rejecting request. API key exceeded {} requests per minute.", per_minute);
+                return Err((hyper::StatusCode::TooManyRequests, errors::api_key_rate_limited(per_minute)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns JSON-RPC error response (the request is not parsed, so the id is unknown).
+fn error_response(status: hyper::StatusCode, error: Error) -> hyper::Response {
+    let output = Output::Failure(Failure {
+        jsonrpc: Some(Version::V2),
+        error,
+        id: Id::Null,
+    });
+    let body = serde_json::to_vec(&output).expect("Output serialization is infallible; qed");
+    hyper::Response::new()
+        .with_status(status)
+        .with_header(hyper::header::ContentType::json())
+        .with_body(body)
+}
+
+impl RequestMiddleware for Middleware {
+    fn on_request(&self, request: hyper::Request) -> http::RequestMiddlewareAction {
+        if *request.method() == hyper::Method::Post {
+            if let Err((status, error)) = self.verify(&request) {
+                return http::RequestMiddlewareAction::Respond {
+                    should_validate_hosts: false,
+                    response: Box::new(future::ok(error_response(status, error))),
+                }
+            }
+        }
+
+        self.ui.on_request(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forwarded_for, percent_decode};
+
+    #[test]
+    fn should_decode_query_values() {
+        assert_eq!(percent_decode("key1"), Some("key1".into()));
+        assert_eq!(percent_decode("a%2Bb%3D%3d+c"), Some("a+b== c".into()));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    #[test]
+    fn should_read_address_added_by_trusted_proxy() {
+        let headers: Vec<&[u8]> = vec![b"1.1.1.1, 2.2.2.2", b"3.3.3.3"];
+        let ip = |proxies| forwarded_for(headers.clone().into_iter(), proxies);
+        assert_eq!(ip(1), Some("3.3.3.3".parse().unwrap()));
+        assert_eq!(ip(2), Some("2.2.2.2".parse().unwrap()));
+        assert_eq!(ip(5), Some("1.1.1.1".parse().unwrap()));
+        assert_eq!(forwarded_for(vec![b"" as &[u8]].into_iter(), 1), None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Wake-up notifications for waiting threads.
+
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+/// Wakes up a thread waiting for changes.
+///
+/// Notifications are not lost: if `notify` is called before `wait`, the next `wait` returns immediately.
+#[derive(Debug, Default)]
+pub struct Notify {
+    changed: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Notify {
+    /// Notifies the waiting thread.
+    pub fn notify(&self) {
+        *self.changed.lock() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Blocks until notified or until the timeout passes (if any).
+    pub fn wait(&self, timeout: Option<Duration>) {
+        let mut changed = self.changed.lock();
+        if !*changed {
+            match timeout {
+                Some(timeout) => {
+                    self.condvar.wait_for(&mut changed, timeout);
+                },
+                None => self.condvar.wait(&mut changed),
+            }
+        }
+        *changed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use super::Notify;
+
+    #[test]
+    fn should_wake_up_waiting_thread() {
+        let notify = Arc::new(Notify::default());
+
+        // Notification before wait is not lost.
+        notify.notify();
+        notify.wait(None);
+
+        // The waiter returns whether it starts waiting before or after the notification.
+        let notify2 = notify.clone();
+        let handle = thread::spawn(move || notify2.wait(None));
+        notify.notify();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn should_return_after_timeout_without_notification() {
+        let notify = Notify::default();
+        notify.wait(Some(Duration::from_millis(1)));
+    }
+}
@@ -1,22 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use rustc_hex::ToHex;
+use tiny_keccak::keccak256;
+
+use gas_price::GasPricePolicy;
+use rate_limit::Limit;
+use rules::Rules;
+use schedule::Schedule;
+use submitter::Queue;
+
+/// Handling of requests targeting blocks or timestamps in the past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum PastTargets {
+    /// Reject the request (default).
+    #[serde(rename="reject")]
+    Reject,
+    /// Submit the transaction immediately (reported in returned id).
+    #[serde(rename="submit")]
+    Submit,
+}
+
+impl Default for PastTargets {
+    fn default() -> Self {
+        PastTargets::Reject
+    }
+}
+
 /// Transaction Scheduler Server options.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Options {
     /// Chain id
     pub chain_id: u64,
+    /// Fork schedule used to compute intrinsic gas
+    pub schedule: Schedule,
     /// Maximal gas of a transaction
     pub max_gas: u64,
     /// Minimal gas price
     pub min_gas_price: u64,
+    /// Policy of computing minimal gas price (never below `min_gas_price`)
+    pub gas_price: GasPricePolicy,
     /// Max number of blocks in future to schedule for.
     pub max_schedule_block: u64,
     /// Max number of seconds in future to schedule for.
     pub max_schedule_seconds: u64,
+    /// Handling of targets in the past (future targets closer than minimal lead time are always rejected).
+    pub past_targets: PastTargets,
+    /// Min number of blocks in future to schedule for.
+    pub min_lead_blocks: u64,
+    /// Min number of seconds in future to schedule for.
+    pub min_lead_seconds: u64,
     /// Require nonce to be equal to current nonce (and not only greater).
     pub strict_nonce: bool,
+    /// Include transactions submitted by the scheduler, but not yet mined, in sender's nonce.
+    pub count_in_flight: bool,
+    /// Max number of transactions scheduled by a single sender (in each queue)
+    pub max_txs_per_sender: usize,
+    /// Restrictions of transactions' recipient, called method and value.
+    pub rules: Rules,
     /// JSON-RPC Listening address
     pub rpc_listen_address: ::std::net::SocketAddr,
     /// JSON-RPC Server threads
     pub rpc_server_threads: usize,
     /// Transactions processing threads
     pub processing_threads: usize,
+    /// JSON-RPC IPC socket path (IPC server is disabled if `None`)
+    pub ipc_path: Option<::std::path::PathBuf>,
+    /// Unix permissions of the IPC socket file
+    pub ipc_permissions: u32,
+    /// Token required to call admin methods over HTTP (disabled if `None`)
+    pub admin_token: Option<String>,
+    /// HTTP API keys
+    pub api_keys: ApiKeys,
+    /// Limit of scheduling requests from a single client IP
+    pub ip_rate_limit: Option<Limit>,
+    /// Limit of scheduling requests from a single sender
+    pub sender_rate_limit: Option<Limit>,
+    /// Number of trusted proxies in front of the server, client IP is read from `X-Forwarded-For` header if non-zero
+    pub trusted_proxies: usize,
+}
+
+/// HTTP API keys configuration.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ApiKeys {
+    /// Reject HTTP requests without a valid API key.
+    pub required: bool,
+    /// Known API keys and their limits.
+    pub keys: HashMap<String, ApiKey>,
+}
+
+impl ApiKeys {
+    const ID_BYTES: usize = 8;
+
+    /// Returns an identifier of given key stored together with scheduled transactions.
+    ///
+    /// The identifier is derived from the hash of the key, so the key itself is never written to disk.
+    pub fn key_id(key: &str) -> String {
+        format!("0x{}", keccak256(key.as_bytes())[..Self::ID_BYTES].to_hex())
+    }
+
+    /// Returns given key id, or the id of given key if it's not an id
+    /// (transactions scheduled by previous versions were stored with the key).
+    pub fn normalize_id(key: String) -> String {
+        let is_id = key.len() == 2 + 2 * Self::ID_BYTES
+            && key.starts_with("0x")
+            && key[2..].chars().all(|c| c.is_digit(16) && !c.is_uppercase());
+        if is_id { key } else { Self::key_id(&key) }
+    }
+}
+
+/// Limits of a single API key.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ApiKey {
+    /// Max number of transactions scheduled with this key at once.
+    pub max_scheduled: Option<usize>,
+    /// Max number of JSON-RPC requests per minute.
+    pub requests_per_minute: Option<u32>,
+    /// Queues this key is allowed to schedule to (all if `None`).
+    pub queues: Option<Vec<Queue>>,
+}
+
+/// Options shared by the verifiers, which can be replaced while the server is running.
+#[derive(Debug)]
+pub struct SharedOptions {
+    options: RwLock<Arc<Options>>,
+}
+
+impl SharedOptions {
+    /// Creates new shared options.
+    pub fn new(options: Options) -> Self {
+        SharedOptions { options: RwLock::new(Arc::new(options)) }
+    }
+
+    /// Returns current options.
+    pub fn get(&self) -> Arc<Options> {
+        self.options.read().clone()
+    }
+
+    /// Replaces the options. Requests already being verified keep using previous ones.
+    ///
+    /// Only verification options are applied, changes of server settings
+    /// (listen address, threads, API keys, rate limits) require restart.
+    pub fn set(&self, options: Options) {
+        *self.options.write() = Arc::new(options);
+    }
 }
@@ -0,0 +1,394 @@
+//! Sender admission policies.
+//!
+//! A policy decides whether a sender is allowed to schedule transactions.
+//! Policies can be combined with `All` and `Any`.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{fmt, fs};
+
+use ethabi;
+use futures::{future, Future};
+use parking_lot::RwLock;
+use serde_json;
+use web3;
+use web3::contract;
+
+use blockchain::Blockchain;
+use cache::Cache;
+use types::{Address, U256};
+
+/// Result of the admission check.
+pub type Admission = Box<Future<Item=bool, Error=contract::Error> + Send>;
+
+/// Sender admission policy.
+pub trait Policy: fmt::Debug + Send + Sync {
+    /// Returns true if given sender is allowed to schedule transactions.
+    fn is_allowed(&self, sender: Address) -> Admission;
+}
+
+/// Requires all of the policies to allow the sender (allows everyone if empty).
+///
+/// Policies are evaluated concurrently.
+#[derive(Debug)]
+pub struct All(pub Vec<Box<Policy>>);
+
+impl Policy for All {
+    fn is_allowed(&self, sender: Address) -> Admission {
+        let checks = self.0.iter().map(|policy| policy.is_allowed(sender)).collect::<Vec<_>>();
+        Box::new(future::join_all(checks).map(|results| results.into_iter().all(|allowed| allowed)))
+    }
+}
+
+/// Requires any of the policies to allow the sender.
+///
+/// Policies are evaluated concurrently. Failing policies are ignored if any other policy allows the sender,
+/// an error is returned only if no policy allowed the sender and some of them failed.
+#[derive(Debug)]
+pub struct Any(pub Vec<Box<Policy>>);
+
+impl Policy for Any {
+    fn is_allowed(&self, sender: Address) -> Admission {
+        let checks = self.0.iter()
+            .map(|policy| policy.is_allowed(sender).then(Ok::<_, contract::Error>))
+            .collect::<Vec<_>>();
+        Box::new(future::join_all(checks).and_then(move |results| {
+            let mut error = None;
+            for result in results {
+                match result {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {},
+                    Err(err) => {
+                        warn!("Admission check of {:?} failed: {:?}", sender, err);
+                        error = Some(err);
+                    },
+                }
+            }
+            match error {
+                Some(err) => Err(err),
+                None => Ok(false),
+            }
+        }))
+    }
+}
+
+/// Caches positive results of the inner policy.
+///
+/// Rejections are not cached, so that a sender is accepted as soon as it gets certified (or buys tokens).
+#[derive(Debug)]
+pub struct Cached<P> {
+    inner: P,
+    cache: Arc<Cache<Address, ()>>,
+}
+
+impl<P: Policy> Cached<P> {
+    /// Wraps given policy with a cache of given capacity and TTL.
+    pub fn new(inner: P, capacity: usize, ttl: Duration) -> Self {
+        Cached {
+            inner,
+            cache: Arc::new(Cache::new(capacity, ttl)),
+        }
+    }
+}
+
+impl<P: Policy> Policy for Cached<P> {
+    fn is_allowed(&self, sender: Address) -> Admission {
+        if self.cache.get(&sender).is_some() {
+            trace!("Returning cached admission for {:?}", sender);
+            return Box::new(future::ok(true));
+        }
+
+        let cache = self.cache.clone();
+        Box::new(self.inner.is_allowed(sender).map(move |allowed| {
+            trace!("Got admission for {:?} = {:?}", sender, allowed);
+            if allowed {
+                cache.insert(sender, ());
+            }
+            allowed
+        }))
+    }
+}
+
+/// Calls a constant contract function `function(address) returns (bool)` with the sender address.
+pub struct ContractCall {
+    blockchain: Arc<Blockchain>,
+    address: Address,
+    abi: Arc<ethabi::Contract>,
+    function: Arc<String>,
+}
+
+impl ContractCall {
+    /// Creates new contract rule calling given function.
+    pub fn new(blockchain: Arc<Blockchain>, address: Address, function: String) -> Result<Self, String> {
+        let abi = json!([{
+            "constant": true,
+            "inputs": [{ "name": "", "type": "address" }],
+            "name": function,
+            "outputs": [{ "name": "", "type": "bool" }],
+            "payable": false,
+            "type": "function",
+        }]);
+        let abi = load_abi(abi.to_string().as_bytes())
+            .map_err(|e| format!("Invalid contract function {}: {}", function, e))?;
+        Ok(ContractCall {
+            blockchain,
+            address,
+            abi: Arc::new(abi),
+            function: Arc::new(function),
+        })
+    }
+
+    /// Creates a rule checking `MultiCertifier` contract at given address.
+    pub fn certifier(blockchain: Arc<Blockchain>, address: Address) -> Self {
+        ContractCall {
+            blockchain,
+            address,
+            abi: Arc::new(load_abi(include_bytes!("./abi/MultiCertifier.json")).expect("Bundled ABI is valid; qed")),
+            function: Arc::new("certified".into()),
+        }
+    }
+}
+
+fn load_abi(json: &[u8]) -> Result<ethabi::Contract, String> {
+    ethabi::Contract::load(json).map_err(|e| format!("{:?}", e))
+}
+
+impl fmt::Debug for ContractCall {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ContractCall")
+            .field("address", &self.address)
+            .field("function", &self.function)
+            .finish()
+    }
+}
+
+impl Policy for ContractCall {
+    fn is_allowed(&self, sender: Address) -> Admission {
+        self.blockchain.call(self.address, self.abi.clone(), self.function.clone(), sender)
+    }
+}
+
+/// Requires the sender to hold at least `min_balance` of ERC-20 tokens or ERC-721 tokens (NFTs).
+///
+/// Both standards expose `balanceOf(address) returns (uint256)`.
+pub struct TokenHolder {
+    blockchain: Arc<Blockchain>,
+    token: Address,
+    min_balance: U256,
+    abi: Arc<ethabi::Contract>,
+    function: Arc<String>,
+}
+
+impl TokenHolder {
+    /// Creates new token holding requirement.
+    pub fn new(blockchain: Arc<Blockchain>, token: Address, min_balance: U256) -> Self {
+        let abi = br#"[{"constant":true,"inputs":[{"name":"","type":"address"}],"name":"balanceOf","outputs":[{"name":"","type":"uint256"}],"payable":false,"type":"function"}]"#;
+        TokenHolder {
+            blockchain,
+            token,
+            min_balance,
+            abi: Arc::new(load_abi(abi).expect("Static ABI is valid; qed")),
+            function: Arc::new("balanceOf".into()),
+        }
+    }
+}
+
+impl fmt::Debug for TokenHolder {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TokenHolder")
+            .field("token", &self.token)
+            .field("min_balance", &self.min_balance)
+            .finish()
+    }
+}
+
+impl Policy for TokenHolder {
+    fn is_allowed(&self, sender: Address) -> Admission {
+        let min_balance = self.min_balance;
+        Box::new(self.blockchain.call(self.token, self.abi.clone(), self.function.clone(), sender)
+            .map(move |balance: web3::types::U256| {
+                let balance: U256 = (*balance).into();
+                balance >= min_balance
+            }))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListsFile {
+    allow: Option<Vec<String>>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct ListsState {
+    modified: Option<SystemTime>,
+    allow: Option<HashSet<Address>>,
+    deny: HashSet<Address>,
+}
+
+/// Static allow and deny lists loaded from a JSON file:
+/// `{ "allow": ["0x..."], "deny": ["0x..."] }`.
+///
+/// Senders on the deny list are always rejected.
+/// If the allow list is present only senders on that list are accepted.
+/// The file is reloaded whenever it's modified.
+#[derive(Debug)]
+pub struct Lists {
+    path: PathBuf,
+    state: RwLock<ListsState>,
+}
+
+impl Lists {
+    /// Loads lists from given file.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, String> {
+        let lists = Lists {
+            path: path.into(),
+            state: Default::default(),
+        };
+        lists.reload()?;
+        Ok(lists)
+    }
+
+    /// Reloads the lists if the file was modified since last load.
+    pub fn reload(&self) -> Result<(), String> {
+        let modified = fs::metadata(&self.path).and_then(|meta| meta.modified()).ok();
+        if modified.is_some() && modified == self.state.read().modified {
+            return Ok(());
+        }
+
+        let file = fs::File::open(&self.path)
+            .map_err(|e| format!("Unable to open {}: {}", self.path.display(), e))?;
+        let lists: ListsFile = serde_json::from_reader(file)
+            .map_err(|e| format!("Invalid lists file {}: {}", self.path.display(), e))?;
+        let allow = match lists.allow {
+            Some(allow) => Some(parse_addresses(&allow)?),
+            None => None,
+        };
+        let deny = parse_addresses(&lists.deny)?;
+
+        info!("Loaded admission lists from {}", self.path.display());
+        *self.state.write() = ListsState { modified, allow, deny };
+        Ok(())
+    }
+}
+
+impl Policy for Lists {
+    fn is_allowed(&self, sender: Address) -> Admission {
+        if let Err(err) = self.reload() {
+            warn!("Keeping previous admission lists: {}", err);
+        }
+
+        let state = self.state.read();
+        let allowed = !state.deny.contains(&sender) && state.allow.as_ref().map_or(true, |allow| allow.contains(&sender));
+        Box::new(future::ok(allowed))
+    }
+}
+
+fn parse_addresses(addresses: &[String]) -> Result<HashSet<Address>, String> {
+    addresses.iter().map(|address| {
+        address.trim_left_matches("0x").parse()
+            .map_err(|e| format!("Invalid address {}: {:?}", address, e))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::{future, Future};
+    use tempdir::TempDir;
+    use web3::contract;
+
+    use types::Address;
+    use super::{Admission, All, Any, Cached, Lists, Policy};
+
+    #[derive(Debug)]
+    struct Fixed(bool);
+
+    impl Policy for Fixed {
+        fn is_allowed(&self, _sender: Address) -> Admission {
+            Box::new(future::ok(self.0))
+        }
+    }
+
+    /// Allows senders listed in the vector and counts the checks.
+    #[derive(Debug)]
+    struct Counting(Vec<Address>, Arc<AtomicUsize>);
+
+    impl Policy for Counting {
+        fn is_allowed(&self, sender: Address) -> Admission {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Box::new(future::ok(self.0.contains(&sender)))
+        }
+    }
+
+    #[test]
+    fn should_cache_only_positive_results() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let cached = Cached::new(Counting(vec![Address::from(1)], checks.clone()), 10, Duration::from_secs(60));
+
+        assert_eq!(cached.is_allowed(Address::from(1)).wait().unwrap(), true);
+        assert_eq!(cached.is_allowed(Address::from(1)).wait().unwrap(), true);
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+        assert_eq!(cached.is_allowed(Address::from(2)).wait().unwrap(), false);
+        assert_eq!(cached.is_allowed(Address::from(2)).wait().unwrap(), false);
+        assert_eq!(checks.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn should_combine_policies() {
+        let sender = Address::default();
+        let all = All(vec![Box::new(Fixed(true)), Box::new(Fixed(false))]);
+        let any = Any(vec![Box::new(Fixed(false)), Box::new(Fixed(true))]);
+        let nested = All(vec![Box::new(any), Box::new(Fixed(true))]);
+
+        assert_eq!(all.is_allowed(sender).wait().unwrap(), false);
+        assert_eq!(nested.is_allowed(sender).wait().unwrap(), true);
+        assert_eq!(All(vec![]).is_allowed(sender).wait().unwrap(), true);
+        assert_eq!(Any(vec![]).is_allowed(sender).wait().unwrap(), false);
+    }
+
+    #[derive(Debug)]
+    struct Failing;
+
+    impl Policy for Failing {
+        fn is_allowed(&self, _sender: Address) -> Admission {
+            Box::new(future::err(contract::Error::from("Node is down")))
+        }
+    }
+
+    #[test]
+    fn should_ignore_failing_policies_if_any_allows() {
+        let sender = Address::default();
+        let allowed = Any(vec![Box::new(Failing), Box::new(Fixed(true))]);
+        let rejected = Any(vec![Box::new(Failing), Box::new(Fixed(false))]);
+
+        assert_eq!(allowed.is_allowed(sender).wait().unwrap(), true);
+        assert!(rejected.is_allowed(sender).wait().is_err());
+    }
+
+    #[test]
+    fn should_check_allow_and_deny_lists() {
+        let tempdir = TempDir::new("").unwrap();
+        let path = tempdir.path().join("lists.json");
+        File::create(&path).unwrap().write_all(br#"{
+            "allow": ["0x0000000000000000000000000000000000000001", "0000000000000000000000000000000000000002"],
+            "deny": ["0x0000000000000000000000000000000000000002"]
+        }"#).unwrap();
+
+        let lists = Lists::new(&path).unwrap();
+
+        assert_eq!(lists.is_allowed(Address::from(1)).wait().unwrap(), true);
+        assert_eq!(lists.is_allowed(Address::from(2)).wait().unwrap(), false);
+        assert_eq!(lists.is_allowed(Address::from(3)).wait().unwrap(), false);
+    }
+}
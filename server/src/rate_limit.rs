@@ -0,0 +1,166 @@
+//! Token-bucket rate limiting.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+use options::Options;
+use types::Address;
+
+/// Number of buckets after which full buckets are pruned.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Rate limit definition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    /// Maximal number of requests in a burst.
+    pub burst: u32,
+    /// Number of requests per minute.
+    pub per_minute: u32,
+}
+
+impl Limit {
+    /// Creates a limit of `per_minute` requests with the same burst size.
+    pub fn per_minute(per_minute: u32) -> Self {
+        Limit { burst: per_minute, per_minute }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Limit the bucket was last checked with (limits may differ between keys).
+    limit: Limit,
+}
+
+/// Token-bucket rate limiter keyed by `K`.
+#[derive(Debug)]
+pub struct RateLimiter<K: Hash + Eq> {
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq> Default for RateLimiter<K> {
+    fn default() -> Self {
+        RateLimiter { buckets: Default::default() }
+    }
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// Takes a token for given key.
+    /// Returns `false` if there are no tokens left.
+    pub fn check(&self, key: K, limit: Limit) -> bool {
+        self.check_at(key, limit, Instant::now())
+    }
+
+    fn check_at(&self, key: K, limit: Limit, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                let limit = bucket.limit;
+                Self::refill(bucket, limit, now) < limit.burst as f64
+            });
+        }
+
+        let bucket = buckets.entry(key).or_insert_with(|| Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+            limit,
+        });
+        Self::refill(bucket, limit, now);
+        bucket.limit = limit;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refill(bucket: &mut Bucket, limit: Limit, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated);
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_minute as f64 / 60.0).min(limit.burst as f64);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+/// Per-IP and per-sender limits of scheduling requests.
+#[derive(Debug)]
+pub struct RateLimits {
+    ip_limit: Option<Limit>,
+    sender_limit: Option<Limit>,
+    ips: RateLimiter<IpAddr>,
+    senders: RateLimiter<Address>,
+}
+
+impl RateLimits {
+    /// Creates new rate limits from options.
+    pub fn new(options: &Options) -> Self {
+        RateLimits {
+            ip_limit: options.ip_rate_limit,
+            sender_limit: options.sender_rate_limit,
+            ips: Default::default(),
+            senders: Default::default(),
+        }
+    }
+
+    /// Takes a token for given client IP.
+    pub fn check_ip(&self, ip: Option<IpAddr>) -> bool {
+        match (self.ip_limit, ip) {
+            (Some(limit), Some(ip)) => self.ips.check(ip, limit),
+            _ => true,
+        }
+    }
+
+    /// Takes a token for given sender.
+    pub fn check_sender(&self, sender: &Address) -> bool {
+        match self.sender_limit {
+            Some(limit) => self.senders.check(*sender, limit),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn should_limit_requests_and_refill() {
+        let limiter = RateLimiter::default();
+        let limit = Limit { burst: 2, per_minute: 60 };
+        let now = Instant::now();
+
+        assert!(limiter.check_at(1, limit, now));
+        assert!(limiter.check_at(1, limit, now));
+        assert!(!limiter.check_at(1, limit, now));
+        // Other keys are not affected.
+        assert!(limiter.check_at(2, limit, now));
+
+        assert!(limiter.check_at(1, limit, now + Duration::from_secs(1)));
+        assert!(!limiter.check_at(1, limit, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn should_prune_full_buckets_using_their_own_limits() {
+        let limiter = RateLimiter::default();
+        let slow = Limit { burst: 2, per_minute: 1 };
+        let fast = Limit { burst: 2, per_minute: 6000 };
+        let now = Instant::now();
+
+        assert!(limiter.check_at(0, slow, now));
+        for key in 1..PRUNE_THRESHOLD + 1 {
+            assert!(limiter.check_at(key, fast, now));
+        }
+        // Fast buckets are full again, the slow one is not.
+        assert!(limiter.check_at(PRUNE_THRESHOLD + 1, fast, now + Duration::from_secs(1)));
+        assert!(limiter.buckets.lock().contains_key(&0));
+        assert_eq!(limiter.buckets.lock().len(), 2);
+    }
+}
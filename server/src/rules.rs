@@ -0,0 +1,178 @@
+//! Restrictions of transactions' recipient, called method and value.
+
+use std::collections::{HashMap, HashSet};
+
+use ethcore::transaction::Action;
+use jsonrpc_core::Error;
+use rustc_hex::FromHex;
+
+use errors;
+use types::{Address, U256};
+
+/// Length of the function selector.
+const SELECTOR_LEN: usize = 4;
+
+/// Rules as specified in the config file (addresses, selectors and values as strings).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesConfig {
+    /// Allow contract creation (defaults to `true`).
+    pub allow_create: Option<bool>,
+    /// Allow only transactions to these addresses.
+    pub allowed_to: Option<Vec<String>>,
+    /// Reject transactions to these addresses.
+    #[serde(default)]
+    pub denied_to: Vec<String>,
+    /// Allowed 4-byte function selectors per contract address.
+    #[serde(default)]
+    pub selectors: HashMap<String, Vec<String>>,
+    /// Maximal transaction value in wei (decimal).
+    pub max_value: Option<String>,
+}
+
+/// Transaction rules.
+///
+/// Default rules allow every transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    allow_create: bool,
+    allowed_to: Option<HashSet<Address>>,
+    denied_to: HashSet<Address>,
+    selectors: HashMap<Address, HashSet<[u8; SELECTOR_LEN]>>,
+    max_value: Option<U256>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            allow_create: true,
+            allowed_to: None,
+            denied_to: Default::default(),
+            selectors: Default::default(),
+            max_value: None,
+        }
+    }
+}
+
+impl Rules {
+    /// Parses rules from config.
+    pub fn from_config(config: &RulesConfig) -> Result<Self, String> {
+        let allowed_to = match config.allowed_to {
+            Some(ref addresses) => Some(addresses.iter().map(|a| parse_address(a)).collect::<Result<_, _>>()?),
+            None => None,
+        };
+        let denied_to = config.denied_to.iter().map(|a| parse_address(a)).collect::<Result<_, _>>()?;
+        let selectors = config.selectors.iter().map(|(address, selectors)| {
+            let selectors = selectors.iter().map(|s| parse_selector(s)).collect::<Result<_, _>>()?;
+            Ok((parse_address(address)?, selectors))
+        }).collect::<Result<_, String>>()?;
+        let max_value = match config.max_value {
+            Some(ref value) => Some(U256::from_dec_str(value).map_err(|e| format!("Invalid max_value {}: {:?}", value, e))?),
+            None => None,
+        };
+
+        Ok(Rules {
+            allow_create: config.allow_create.unwrap_or(true),
+            allowed_to,
+            denied_to,
+            selectors,
+            max_value,
+        })
+    }
+
+    /// Verifies transaction recipient, called method and value.
+    ///
+    /// If selectors are configured for the recipient the transaction has to call one of them
+    /// (plain transfers to such contract are rejected).
+    pub fn verify(&self, action: &Action, data: &[u8], value: U256) -> Result<(), Error> {
+        if let Some(max_value) = self.max_value {
+            if value > max_value {
+                return Err(errors::value_too_high(max_value, value));
+            }
+        }
+
+        let to = match *action {
+            Action::Create if self.allow_create => return Ok(()),
+            Action::Create => return Err(errors::contract_creation_not_allowed()),
+            Action::Call(ref to) => to,
+        };
+
+        let allowed = !self.denied_to.contains(to) && self.allowed_to.as_ref().map_or(true, |allowed| allowed.contains(to));
+        if !allowed {
+            return Err(errors::recipient_not_allowed(to));
+        }
+
+        if let Some(selectors) = self.selectors.get(to) {
+            if data.len() < SELECTOR_LEN {
+                return Err(errors::method_not_allowed(to, None));
+            }
+            let mut selector = [0; SELECTOR_LEN];
+            selector.copy_from_slice(&data[..SELECTOR_LEN]);
+            if !selectors.contains(&selector) {
+                return Err(errors::method_not_allowed(to, Some(&selector)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address, String> {
+    address.trim_left_matches("0x").parse()
+        .map_err(|e| format!("Invalid address {}: {:?}", address, e))
+}
+
+fn parse_selector(selector: &str) -> Result<[u8; SELECTOR_LEN], String> {
+    let bytes: Vec<u8> = selector.trim_left_matches("0x").from_hex()
+        .map_err(|e| format!("Invalid selector {}: {:?}", selector, e))?;
+    if bytes.len() != SELECTOR_LEN {
+        return Err(format!("Invalid selector {}: expected {} bytes", selector, SELECTOR_LEN));
+    }
+    let mut result = [0; SELECTOR_LEN];
+    result.copy_from_slice(&bytes);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethcore::transaction::Action;
+    use errors::codes;
+    use types::{Address, U256};
+    use super::{Rules, RulesConfig};
+
+    fn rules() -> Rules {
+        let mut config = RulesConfig::default();
+        config.allow_create = Some(false);
+        config.denied_to = vec!["0x0000000000000000000000000000000000000002".into()];
+        config.selectors.insert("0000000000000000000000000000000000000001".into(), vec!["0xa9059cbb".into()]);
+        config.max_value = Some("1000".into());
+        Rules::from_config(&config).unwrap()
+    }
+
+    fn code(result: Result<(), ::jsonrpc_core::Error>) -> Option<::jsonrpc_core::ErrorCode> {
+        result.err().map(|err| err.code)
+    }
+
+    #[test]
+    fn should_allow_everything_by_default() {
+        let rules = Rules::default();
+
+        assert!(rules.verify(&Action::Create, &[], U256::max_value()).is_ok());
+        assert!(rules.verify(&Action::Call(Address::from(1)), &[1, 2], U256::zero()).is_ok());
+    }
+
+    #[test]
+    fn should_verify_recipient_method_and_value() {
+        use jsonrpc_core::ErrorCode::ServerError;
+        let rules = rules();
+        let contract = Action::Call(Address::from(1));
+
+        assert_eq!(code(rules.verify(&contract, &[0xa9, 0x05, 0x9c, 0xbb, 0], U256::from(1000))), None);
+        assert_eq!(code(rules.verify(&contract, &[0xa9, 0x05, 0x9c, 0xbb], U256::from(1001))), Some(ServerError(codes::VALUE_TOO_HIGH)));
+        assert_eq!(code(rules.verify(&contract, &[0x01, 0x02, 0x03, 0x04], U256::zero())), Some(ServerError(codes::TRANSACTION_NOT_ALLOWED)));
+        assert_eq!(code(rules.verify(&contract, &[], U256::zero())), Some(ServerError(codes::TRANSACTION_NOT_ALLOWED)));
+        assert_eq!(code(rules.verify(&Action::Call(Address::from(2)), &[], U256::zero())), Some(ServerError(codes::TRANSACTION_NOT_ALLOWED)));
+        assert_eq!(code(rules.verify(&Action::Call(Address::from(3)), &[], U256::zero())), None);
+        assert_eq!(code(rules.verify(&Action::Create, &[], U256::zero())), Some(ServerError(codes::TRANSACTION_NOT_ALLOWED)));
+    }
+}
@@ -0,0 +1,187 @@
+//! Fork schedule used to compute intrinsic gas of transactions.
+
+use ethcore::transaction::Action;
+
+/// Gas costs of a transaction (independent of its execution).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasSchedule {
+    /// Base gas of a call.
+    pub tx_gas: u64,
+    /// Base gas of contract creation.
+    pub tx_create_gas: u64,
+    /// Gas per zero byte of data.
+    pub tx_data_zero_gas: u64,
+    /// Gas per non-zero byte of data.
+    pub tx_data_non_zero_gas: u64,
+    /// Gas per 32-byte word of contract creation code (EIP-3860).
+    pub initcode_word_gas: u64,
+}
+
+/// Frontier costs.
+const FRONTIER: GasSchedule = GasSchedule {
+    tx_gas: 21_000,
+    tx_create_gas: 21_000,
+    tx_data_zero_gas: 4,
+    tx_data_non_zero_gas: 68,
+    initcode_word_gas: 0,
+};
+
+impl GasSchedule {
+    /// Returns intrinsic gas of a transaction.
+    pub fn intrinsic_gas(&self, action: &Action, data: &[u8]) -> u64 {
+        let (base, initcode) = match *action {
+            Action::Create => (self.tx_create_gas, self.initcode_word_gas * ((data.len() as u64 + 31) / 32)),
+            Action::Call(_) => (self.tx_gas, 0),
+        };
+
+        data.iter().fold(
+            base + initcode,
+            |acc, b| acc + if *b == 0 { self.tx_data_zero_gas } else { self.tx_data_non_zero_gas },
+        )
+    }
+}
+
+/// Fork activation point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    /// Activated at given block number.
+    Block(u64),
+    /// Activated at given unix timestamp.
+    Timestamp(u64),
+}
+
+impl Activation {
+    fn is_active(&self, block: u64, timestamp: u64) -> bool {
+        match *self {
+            Activation::Block(number) => block >= number,
+            Activation::Timestamp(time) => timestamp >= time,
+        }
+    }
+}
+
+/// Fork as specified in the config file.
+///
+/// Unset costs are inherited from the preceding fork.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForkConfig {
+    /// Activation block.
+    pub block: Option<u64>,
+    /// Activation timestamp.
+    pub timestamp: Option<u64>,
+    /// Base gas of a call.
+    pub tx_gas: Option<u64>,
+    /// Base gas of contract creation.
+    pub tx_create_gas: Option<u64>,
+    /// Gas per zero byte of data.
+    pub tx_data_zero_gas: Option<u64>,
+    /// Gas per non-zero byte of data.
+    pub tx_data_non_zero_gas: Option<u64>,
+    /// Gas per 32-byte word of contract creation code.
+    pub initcode_word_gas: Option<u64>,
+}
+
+/// Gas schedules of subsequent forks of a chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    forks: Vec<(Activation, GasSchedule)>,
+}
+
+impl Schedule {
+    /// Returns built-in schedule of given chain.
+    ///
+    /// Unknown chains are assumed to have all forks active from genesis.
+    pub fn for_chain(chain_id: u64) -> Self {
+        let homestead = GasSchedule { tx_create_gas: 53_000, ..FRONTIER };
+        let istanbul = GasSchedule { tx_data_non_zero_gas: 16, ..homestead };
+        let shanghai = GasSchedule { initcode_word_gas: 2, ..istanbul };
+
+        let forks = match chain_id {
+            // Mainnet
+            1 => vec![
+                (Activation::Block(0), FRONTIER),
+                (Activation::Block(1_150_000), homestead),
+                (Activation::Block(9_069_000), istanbul),
+                (Activation::Timestamp(1_681_338_455), shanghai),
+            ],
+            // Kovan
+            42 => vec![
+                (Activation::Block(0), homestead),
+                (Activation::Block(14_111_141), istanbul),
+            ],
+            _ => vec![(Activation::Block(0), shanghai)],
+        };
+
+        Schedule { forks }
+    }
+
+    /// Creates schedule from the config (costs of the first fork default to Frontier).
+    pub fn from_config(forks: &[ForkConfig]) -> Result<Self, String> {
+        let mut schedule = FRONTIER;
+        let forks = forks.iter().map(|fork| {
+            let activation = match (fork.block, fork.timestamp) {
+                (Some(block), None) => Activation::Block(block),
+                (None, Some(timestamp)) => Activation::Timestamp(timestamp),
+                _ => return Err("Exactly one of fork block or timestamp has to be specified.".to_owned()),
+            };
+
+            schedule = GasSchedule {
+                tx_gas: fork.tx_gas.unwrap_or(schedule.tx_gas),
+                tx_create_gas: fork.tx_create_gas.unwrap_or(schedule.tx_create_gas),
+                tx_data_zero_gas: fork.tx_data_zero_gas.unwrap_or(schedule.tx_data_zero_gas),
+                tx_data_non_zero_gas: fork.tx_data_non_zero_gas.unwrap_or(schedule.tx_data_non_zero_gas),
+                initcode_word_gas: fork.initcode_word_gas.unwrap_or(schedule.initcode_word_gas),
+            };
+            Ok((activation, schedule))
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        if forks.is_empty() {
+            return Err("At least one fork has to be specified.".into());
+        }
+
+        Ok(Schedule { forks })
+    }
+
+    /// Returns gas schedule active at given block and timestamp.
+    ///
+    /// Forks are expected to be ordered, the last active one is used.
+    pub fn at(&self, block: u64, timestamp: u64) -> GasSchedule {
+        self.forks.iter()
+            .filter(|&&(activation, _)| activation.is_active(block, timestamp))
+            .last()
+            .map(|&(_, schedule)| schedule)
+            .unwrap_or(FRONTIER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethcore::transaction::Action;
+    use types::Address;
+    use super::{ForkConfig, Schedule};
+
+    #[test]
+    fn should_pick_schedule_by_block_and_timestamp() {
+        let mainnet = Schedule::for_chain(1);
+        let call = Action::Call(Address::default());
+        let data = [0, 1, 2];
+
+        assert_eq!(mainnet.at(1_000_000, 0).intrinsic_gas(&Action::Create, &data), 21_000 + 4 + 2 * 68);
+        assert_eq!(mainnet.at(1_150_000, 0).intrinsic_gas(&Action::Create, &data), 53_000 + 4 + 2 * 68);
+        assert_eq!(mainnet.at(9_069_000, 0).intrinsic_gas(&call, &data), 21_000 + 4 + 2 * 16);
+        assert_eq!(mainnet.at(17_034_870, 1_681_338_455).intrinsic_gas(&Action::Create, &data), 53_000 + 2 + 4 + 2 * 16);
+    }
+
+    #[test]
+    fn should_inherit_costs_from_previous_fork() {
+        let schedule = Schedule::from_config(&[
+            ForkConfig { block: Some(0), tx_create_gas: Some(53_000), ..Default::default() },
+            ForkConfig { block: Some(10), tx_data_non_zero_gas: Some(16), ..Default::default() },
+        ]).unwrap();
+
+        assert_eq!(schedule.at(5, 0).tx_data_non_zero_gas, 68);
+        assert_eq!(schedule.at(10, 0).tx_create_gas, 53_000);
+        assert_eq!(schedule.at(10, 0).tx_data_non_zero_gas, 16);
+        assert!(Schedule::from_config(&[ForkConfig::default()]).is_err());
+    }
+}
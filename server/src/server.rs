@@ -1,105 +1,507 @@
 //! JSON-RPC server
 
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use futures::Future;
 use futures::future::{self, Either};
 use futures_cpupool::CpuPool;
-use jsonrpc_core::{Value, IoHandler, Params};
-use jsonrpc_http_server::{Server, Error, ServerBuilder};
+use jsonrpc_core::{Error as RpcError, Value, MetaIoHandler, Metadata, Params};
+use jsonrpc_http_server::{self as http, hyper, ServerBuilder};
+use jsonrpc_ipc_server as ipc;
+use parking_lot::Mutex;
 use serde_json;
 
+use admin::{self, Admin};
 use blockchain::Blockchain;
 use database::{self, Database};
 use errors;
-use options::Options;
+use gas_price::GasPriceOracle;
+use middleware::{self, Middleware};
+use options::{ApiKeys, SharedOptions};
+use policy::Policy;
+use rate_limit::RateLimits;
+use submitter::{Control, Queue};
 use types::{Bytes, Condition, TransactionId};
-use verifier::Verifier;
+use verifier::{self, Check, Report, Verifier};
 
-/// Starts the JSON-RPC server.
+mod error {
+    #![allow(unknown_lints)]
+    #![allow(missing_docs)]
+    error_chain! {
+        foreign_links {
+            Io(::std::io::Error);
+            Http(::jsonrpc_http_server::Error);
+        }
+    }
+}
+
+pub use self::error::*;
+
+/// Request metadata.
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+    /// Whether the request is allowed to call `admin_*` methods.
+    pub admin: bool,
+    /// API key of the request (the secret sent by the client).
+    pub api_key: Option<String>,
+    /// Client IP address (HTTP only).
+    pub remote_ip: Option<IpAddr>,
+}
+
+impl Metadata for Meta {}
+
+/// Re-reads the configuration and applies options that can change at runtime.
+///
+/// Returns names of changed settings that require restart to take effect.
+pub type Reload = Fn() -> ::std::result::Result<Vec<String>, String> + Send + Sync;
+
+/// Components serving a single chain.
+#[derive(Clone)]
+pub struct Chain {
+    /// Chain id (requests are routed by the chain id of the transaction signature).
+    pub chain_id: u64,
+    /// Block number queue.
+    pub block_db: Arc<Database>,
+    /// Timestamp queue.
+    pub timestamp_db: Arc<Database>,
+    /// Underpriced transactions skipped from the block number queue.
+    pub block_underpriced: Arc<Database>,
+    /// Underpriced transactions skipped from the timestamp queue.
+    pub timestamp_underpriced: Arc<Database>,
+    /// Cached blockchain state.
+    pub blockchain: Arc<Blockchain>,
+    /// Runtime control of the submitters.
+    pub control: Arc<Control>,
+    /// Sender admission policy.
+    pub policy: Arc<Policy>,
+    /// Minimal gas price.
+    pub gas_price: Arc<GasPriceOracle>,
+    /// Verification options.
+    pub options: Arc<SharedOptions>,
+}
+
+/// Running JSON-RPC servers.
+pub struct Servers {
+    http: http::Server,
+    ipc: Option<ipc::Server>,
+}
+
+impl Servers {
+    /// Blocks until the HTTP server is closed.
+    pub fn wait(self) {
+        self.http.wait()
+    }
+
+    /// Stops accepting new requests.
+    pub fn close(self) {
+        info!("Closing JSON-RPC servers.");
+        if let Some(ipc) = self.ipc {
+            ipc.close();
+        }
+        self.http.close();
+    }
+}
+
+/// Starts the JSON-RPC servers.
+///
+/// Both servers expose public and `admin_*` methods.
+/// Admin methods are always allowed over IPC (access is controlled with file permissions)
+/// and over HTTP only if the request carries `Authorization: Bearer <admin_token>` header.
+///
+/// HTTP requests are additionaly subject to API keys verification (see `Options::api_keys`).
+///
+/// Verification options can be replaced at runtime (see `SharedOptions`), `reload` is exposed
+/// as `admin_reloadConfig` method.
+///
+/// Requests are routed to one of the `chains` by an explicit chain id parameter or the chain id
+/// of the transaction signature (EIP-155). The first chain is the default one: it serves ids issued
+/// without a chain and, if it's the only chain, all transactions.
+/// RPC settings (address, threads, API keys, etc.) are taken from the options of the first chain.
 pub fn start(
+    chains: Vec<Chain>,
+    reload: Option<Arc<Reload>>,
+) -> Result<Servers> {
+    let options = match chains.first() {
+        Some(chain) => chain.options.get(),
+        None => bail!("At least one chain has to be configured."),
+    };
+    let api_keys = Arc::new(options.api_keys.clone());
+    let limits = Arc::new(RateLimits::new(&options));
+    let rpc = Rpc {
+        api_keys: api_keys.clone(),
+        reservations: Default::default(),
+        limits: limits.clone(),
+        pool: CpuPool::new(options.processing_threads),
+        chains: Arc::new(Chains {
+            default: chains[0].chain_id,
+            chains: chains.iter().map(|chain| (chain.chain_id, ChainRpc {
+                chain_id: chain.chain_id,
+                block_verifier: Arc::new(Verifier::new_block(
+                    chain.blockchain.clone(), chain.block_db.clone(), limits.clone(), chain.policy.clone(), chain.gas_price.clone(), chain.options.clone(),
+                )),
+                timestamp_verifier: Arc::new(Verifier::new_timestamp(
+                    chain.blockchain.clone(), chain.timestamp_db.clone(), limits.clone(), chain.policy.clone(), chain.gas_price.clone(), chain.options.clone(),
+                )),
+                block_db: chain.block_db.clone(),
+                timestamp_db: chain.timestamp_db.clone(),
+            })).collect(),
+        }),
+    };
+    let admin = Admin::new(chains, reload);
+
+    let mut io = MetaIoHandler::default();
+    rpc.extend(&mut io);
+    admin.extend(&mut io);
+
+    let admin_token = options.admin_token.clone();
+    let trusted_proxies = options.trusted_proxies;
+    let http = ServerBuilder::new(io)
+        // don't keep alive, since we're usually doing only one request
+        .keep_alive(false)
+        // enable cors for all domains
+        .cors(None.into())
+        .request_middleware(Middleware::new(api_keys))
+        .meta_extractor(move |request: &hyper::Request| Meta {
+            admin: is_admin(request, admin_token.as_ref()),
+            api_key: middleware::api_key(request),
+            remote_ip: middleware::client_ip(request, trusted_proxies),
+        })
+        .threads(options.rpc_server_threads)
+        .start_http(&options.rpc_listen_address)?;
+
+    let ipc = match options.ipc_path {
+        None => None,
+        Some(ref path) => {
+            let mut io = MetaIoHandler::default();
+            rpc.extend(&mut io);
+            admin.extend(&mut io);
+
+            // Permissions are applied after the socket is created: changing the process umask instead
+            // would affect files created by other threads at the same time.
+            let server = ipc::ServerBuilder::new(io)
+                .session_meta_extractor(|_: &ipc::RequestContext| Meta { admin: true, api_key: None, remote_ip: None })
+                .start(&path.to_string_lossy())?;
+            set_permissions(path, options.ipc_permissions)?;
+            info!("IPC server listening at {}", path.display());
+            Some(server)
+        },
+    };
+
+    Ok(Servers {
+        http,
+        ipc,
+    })
+}
+
+fn is_admin(request: &hyper::Request, admin_token: Option<&String>) -> bool {
+    use self::hyper::header::{Authorization, Bearer};
+
+    match (admin_token, request.headers().get::<Authorization<Bearer>>()) {
+        (Some(token), Some(auth)) => constant_time_eq(auth.token.as_bytes(), token.as_bytes()),
+        _ => false,
+    }
+}
+
+/// Compares given byte slices in time depending only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &::std::path::Path, mode: u32) -> ::std::io::Result<()> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &::std::path::Path, _mode: u32) -> ::std::io::Result<()> {
+    Ok(())
+}
+
+/// Verifiers and queues of a single chain.
+struct ChainRpc {
+    chain_id: u64,
+    block_verifier: Arc<Verifier>,
+    timestamp_verifier: Arc<Verifier>,
     block_db: Arc<Database>,
     timestamp_db: Arc<Database>,
-    blockchain: Arc<Blockchain>,
-    options: Options,
-) -> Result<Server, Error> {
-    let pool = CpuPool::new(options.processing_threads);
-    let block_verifier = Arc::new(Verifier::new_block(blockchain.clone(), block_db.clone(), options.clone()));
-    let timestamp_verifier = Arc::new(Verifier::new_timestamp(blockchain, timestamp_db.clone(), options.clone()));
-
-    let mut io = IoHandler::default();
-    let block_db1 = block_db.clone();
-    let timestamp_db1 = timestamp_db.clone();
-    io.add_method("cancel", move |params: Params| {
-        trace!("Incoming cancel request: {:?}", params);
-        let (id, ) = match params.parse::<(Bytes, )>() {
-            Ok(res) => res,
-            Err(err) => return future::err(err),
-        }
-        ;
-        let id = match TransactionId::from_bytes(id) {
-            Some(id) => id,
-            None => return future::err(errors::transaction("Invalid id")),
-        };
+}
 
-        let result = if id.is_timestamp {
-            timestamp_db1.remove(&id.num, &id.hash)
+impl ChainRpc {
+    fn queue(&self, is_timestamp: bool) -> (Arc<Verifier>, Arc<Database>) {
+        if is_timestamp {
+            (self.timestamp_verifier.clone(), self.timestamp_db.clone())
         } else {
-            block_db1.remove(&id.num, &id.hash)
-        };
+            (self.block_verifier.clone(), self.block_db.clone())
+        }
+    }
 
-        match result {
-            Err(err) => future::err(errors::transaction(err)),
-            Ok(None) => future::err(errors::transaction("Not found")),
-            Ok(Some(_)) => future::ok(Value::String("ok".into())),
+    fn database(&self, is_timestamp: bool) -> &Database {
+        if is_timestamp { &self.timestamp_db } else { &self.block_db }
+    }
+}
+
+/// Served chains.
+struct Chains {
+    default: u64,
+    chains: BTreeMap<u64, ChainRpc>,
+}
+
+impl Chains {
+    /// Returns chain with given id (or the default one).
+    fn get(&self, chain_id: Option<u64>) -> ::std::result::Result<&ChainRpc, RpcError> {
+        let chain_id = chain_id.unwrap_or(self.default);
+        self.chains.get(&chain_id).ok_or_else(|| errors::unsupported_chain(chain_id))
+    }
+
+    /// Selects chain of the transaction by explicit chain id or the chain id of the signature.
+    fn route(&self, explicit: Option<u64>, transaction: &Bytes) -> ::std::result::Result<&ChainRpc, RpcError> {
+        // A single chain verifies the chain id itself (rejecting transactions for other chains).
+        if explicit.is_none() && self.chains.len() == 1 {
+            return self.get(None);
         }
-    });
-    io.add_method("scheduleTransaction", move |params: Params| {
-        trace!("Incoming request: {:?}", params);
-        let (condition, transaction) = match params.parse::<(Condition, Bytes)>() {
-            Ok(res) => res,
-            Err(err) => return Either::A(future::err(err)),
-        };
 
-        let block_verifier = block_verifier.clone();
-        let timestamp_verifier = timestamp_verifier.clone();
-        let block_db = block_db.clone();
-        let timestamp_db = timestamp_db.clone();
-        Either::B(pool.spawn_fn(move || {
-            debug!("Verifying request: {:?}", condition);
-            let (is_timestamp, num, verifier, db) = match condition {
-                Condition::Number(block_number) => (false, block_number, block_verifier, block_db),
-                Condition::Timestamp(time) => (true, time, timestamp_verifier, timestamp_db),
+        match explicit {
+            Some(chain_id) => self.get(Some(chain_id)),
+            None => match verifier::chain_id(transaction)? {
+                Some(chain_id) => self.get(Some(chain_id)),
+                None => Err(errors::chain_required()),
+            },
+        }
+    }
+}
+
+/// Parses `(condition, transaction)` parameters followed by an optional chain id.
+///
+/// Returns the condition together with its target (see `Condition::target`).
+fn transaction_params(params: Params) -> ::std::result::Result<(Condition, (bool, u64), Bytes, Option<u64>), RpcError> {
+    let (params, chain_id) = admin::chain_id_param(params, 2)?;
+    let (condition, transaction) = params.parse::<(Condition, Bytes)>()?;
+    let target = condition.target().ok_or_else(errors::invalid_condition)?;
+    Ok((condition, target, transaction, chain_id))
+}
+
+/// Public JSON-RPC methods.
+#[derive(Clone)]
+struct Rpc {
+    api_keys: Arc<ApiKeys>,
+    reservations: Arc<Mutex<HashMap<String, usize>>>,
+    limits: Arc<RateLimits>,
+    pool: CpuPool,
+    chains: Arc<Chains>,
+}
+
+impl Rpc {
+    fn extend(&self, io: &mut MetaIoHandler<Meta>) {
+        let chains = self.chains.clone();
+        io.add_method("cancel", move |params: Params| {
+            trace!("Incoming cancel request: {:?}", params);
+            let (id, ) = match params.parse::<(Bytes, )>() {
+                Ok(res) => res,
+                Err(err) => return future::err(err),
+            }
+            ;
+            let id = match TransactionId::from_bytes(id) {
+                Some(id) => id,
+                None => return future::err(errors::invalid_id()),
             };
 
-            verifier.verify(num, transaction)
-                .and_then(move |(num, transaction)| {
-                    let hash = *transaction.hash();
-                    if let Err(e) = db.insert(num, transaction) {
-                        if let &database::ErrorKind::SenderExists = e.kind() {
-                            warn!("DB sender exists: {}", e);
-                        } else {
+            let result = match chains.get(id.chain_id) {
+                Ok(chain) => chain.database(id.is_timestamp).remove(&id.num, &id.hash),
+                Err(err) => return future::err(err),
+            };
+
+            match result {
+                Err(err) => future::err(errors::internal(err)),
+                Ok(None) => future::err(errors::not_found()),
+                Ok(Some(_)) => future::ok(Value::String("ok".into())),
+            }
+        });
+
+        let chains = self.chains.clone();
+        let limits = self.limits.clone();
+        io.add_method_with_meta("status", move |params: Params, meta: Meta| {
+            trace!("Incoming status request: {:?}", params);
+            if !limits.check_ip(meta.remote_ip) {
+                debug!("Rejecting status request. Client exceeded rate limit: {:?}", meta.remote_ip);
+                return future::err(errors::ip_rate_limited());
+            }
+            let (id, ) = match params.parse::<(Bytes, )>() {
+                Ok(res) => res,
+                Err(err) => return future::err(err),
+            };
+            let id = match TransactionId::from_bytes(id) {
+                Some(id) => id,
+                None => return future::err(errors::invalid_id()),
+            };
+
+            let result = match chains.get(id.chain_id) {
+                Ok(chain) => chain.database(id.is_timestamp).status(id.num, &id.hash),
+                Err(err) => return future::err(err),
+            };
+
+            match result {
+                Err(err) => future::err(errors::internal(err)),
+                Ok(status) => future::ok(serde_json::to_value(&status).expect("Status serialization is infallible.")),
+            }
+        });
+
+        let rpc = self.clone();
+        io.add_method_with_meta("scheduleTransaction", move |params: Params, meta: Meta| {
+            trace!("Incoming request: {:?}", params);
+            let (condition, (is_timestamp, num), transaction, chain_id) = match transaction_params(params) {
+                Ok(res) => res,
+                Err(err) => return Either::A(future::err(err)),
+            };
+            if !rpc.limits.check_ip(meta.remote_ip) {
+                debug!("Rejecting request. Client exceeded rate limit: {:?}", meta.remote_ip);
+                return Either::A(future::err(errors::ip_rate_limited()));
+            }
+            let reservation = match rpc.verify_api_key(meta.api_key.as_ref(), is_timestamp) {
+                Ok(reservation) => reservation,
+                Err(err) => return Either::A(future::err(err)),
+            };
+            // Only the id of the key is stored with the transaction.
+            let api_key = meta.api_key.as_ref().map(|key| ApiKeys::key_id(key));
+            let (chain_id, (verifier, db)) = match rpc.chains.route(chain_id, &transaction) {
+                Ok(chain) => (chain.chain_id, chain.queue(is_timestamp)),
+                Err(err) => return Either::A(future::err(err)),
+            };
+
+            Either::B(rpc.pool.spawn_fn(move || {
+                debug!("Verifying request: {:?} (chain {})", condition, chain_id);
+
+                verifier.verify(num, transaction)
+                    .and_then(move |(num, immediate, transaction)| {
+                        let transaction = transaction.with_api_key(api_key);
+                        let hash = *transaction.hash();
+                        let inserted = db.insert(num, transaction);
+                        // The transaction is counted by the database now (if inserted).
+                        drop(reservation);
+                        if let Err(e) = inserted {
+                            if let &database::ErrorKind::SenderExists = e.kind() {
+                                warn!("DB sender exists: {}", e);
+                                return Err(errors::too_many_transactions())
+                            }
                             warn!("DB write error: {:?}", e);
+                            return Err(errors::internal(e))
+                        }
+                        if immediate {
+                            info!("[{:?}] Target in the past. Scheduled for immediate submission at {} (chain {})", hash, num, chain_id);
+                        } else {
+                            info!("[{:?}] Scheduled for {} (chain {})", hash, num, chain_id);
                         }
-                        return Err(errors::internal(e))
-                    }
-                    info!("[{:?}] Scheduled for {}", hash, num);
-                    // TODO [ToDr] After transactions are submitted make sure they are mined, if not - resubmit.
-                    Ok(serde_json::to_value(&TransactionId {
-                        is_timestamp,
-                        num,
-                        hash
-                    }.to_bytes()).expect("Bytes serialization is infallible."))
+                        // TODO [ToDr] After transactions are submitted make sure they are mined, if not - resubmit.
+                        Ok(serde_json::to_value(&TransactionId {
+                            is_timestamp,
+                            immediate,
+                            num,
+                            hash,
+                            chain_id: Some(chain_id),
+                        }.to_bytes()).expect("Bytes serialization is infallible."))
+                    })
+            }))
+        });
+
+        let rpc = self.clone();
+        io.add_method_with_meta("verifyTransaction", move |params: Params, meta: Meta| {
+            trace!("Incoming dry-run request: {:?}", params);
+            let (condition, (is_timestamp, num), transaction, chain_id) = match transaction_params(params) {
+                Ok(res) => res,
+                Err(err) => return Either::A(future::err(err)),
+            };
+            if !rpc.limits.check_ip(meta.remote_ip) {
+                debug!("Rejecting request. Client exceeded rate limit: {:?}", meta.remote_ip);
+                return Either::A(future::err(errors::ip_rate_limited()));
+            }
+            // The request itself is rejected, so the transaction isn't verified at all.
+            if let Err(err) = rpc.verify_api_key(meta.api_key.as_ref(), is_timestamp) {
+                let report = Report::new(Some(Check::new("apiKey", Err(err))).into_iter().chain(verifier::skipped_checks()).collect());
+                return Either::A(future::ok(serde_json::to_value(&report).expect("Report serialization is infallible.")));
+            }
+            let api_key = Check::new("apiKey", Ok(()));
+            let (chain_id, (verifier, _)) = match rpc.chains.route(chain_id, &transaction) {
+                Ok(chain) => (chain.chain_id, chain.queue(is_timestamp)),
+                Err(err) => return Either::A(future::err(err)),
+            };
+
+            Either::B(rpc.pool.spawn_fn(move || {
+                debug!("Dry-run verifying request: {:?} (chain {})", condition, chain_id);
+
+                verifier.dry_run(num, transaction).map(move |checks| {
+                    let report = Report::new(Some(api_key).into_iter().chain(checks).collect());
+                    serde_json::to_value(&report).expect("Report serialization is infallible.")
                 })
+            }))
+        });
+    }
+
+    /// Verifies limits of the API key.
+    ///
+    /// If the number of scheduled transactions is limited, a slot is reserved for the transaction
+    /// until the returned `Reservation` is dropped, so that concurrent requests can't exceed the limit.
+    fn verify_api_key(&self, api_key: Option<&String>, is_timestamp: bool) -> ::std::result::Result<Option<Reservation>, RpcError> {
+        let (key, limits) = match api_key.and_then(|key| self.api_keys.keys.get(key).map(|limits| (key, limits))) {
+            Some(res) => res,
+            None => return Ok(None),
+        };
+
+        if let Some(ref queues) = limits.queues {
+            let queue = if is_timestamp { Queue::Timestamp } else { Queue::Block };
+            if !queues.contains(&queue) {
+                return Err(errors::queue_not_allowed(queue));
+            }
+        }
+
+        let max_scheduled = match limits.max_scheduled {
+            Some(max_scheduled) => max_scheduled,
+            None => return Ok(None),
+        };
+
+        let id = ApiKeys::key_id(key);
+        let mut reservations = self.reservations.lock();
+        let reserved = reservations.get(&id).cloned().unwrap_or(0);
+        let scheduled = reserved + self.chains.chains.values()
+            .map(|chain| chain.block_db.api_key_count(&id) + chain.timestamp_db.api_key_count(&id))
+            .sum::<usize>();
+        if scheduled >= max_scheduled {
+            debug!("Rejecting request. API key already has {} transactions scheduled.", scheduled);
+            return Err(errors::api_key_quota(max_scheduled));
+        }
+
+        *reservations.entry(id.clone()).or_insert(0) += 1;
+        Ok(Some(Reservation {
+            id,
+            reservations: self.reservations.clone(),
         }))
-    });
+    }
+}
 
-    ServerBuilder::new(io)
-        // don't keep alive, since we're usually doing only one request
-        .keep_alive(false)
-        // enable cors for all domains
-        .cors(None.into())
-        .request_middleware(::ui::Ui::default())
-        .threads(options.rpc_server_threads)
-        .start_http(&options.rpc_listen_address)
+/// A slot of API key quota taken by a transaction being scheduled.
+struct Reservation {
+    id: String,
+    reservations: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut reservations = self.reservations.lock();
+        let remove = match reservations.get_mut(&self.id) {
+            Some(reserved) => {
+                *reserved -= 1;
+                *reserved == 0
+            },
+            None => false,
+        };
+        if remove {
+            reservations.remove(&self.id);
+        }
+    }
 }
@@ -0,0 +1,126 @@
+//! Building and signing transactions for scheduling.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use ethcore::transaction::{Action, Transaction as UnsignedTransaction};
+use ethkey::{KeyPair, Secret};
+use ethstore::Crypto;
+use serde_json::{self, Value};
+
+use types::{Address, Transaction, U256};
+
+/// Parameters of a transaction to sign.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionRequest {
+    /// Recipient (contract creation if `None`).
+    pub to: Option<Address>,
+    /// Transferred value.
+    pub value: U256,
+    /// Transaction data.
+    pub data: Vec<u8>,
+    /// Gas limit.
+    pub gas: U256,
+    /// Gas price.
+    pub gas_price: U256,
+    /// Sender nonce.
+    pub nonce: U256,
+}
+
+impl TransactionRequest {
+    /// Returns action of the transaction.
+    pub fn action(&self) -> Action {
+        match self.to {
+            Some(to) => Action::Call(to),
+            None => Action::Create,
+        }
+    }
+}
+
+/// Signs transactions with a single key.
+pub struct Signer {
+    secret: Secret,
+    address: Address,
+}
+
+impl Signer {
+    /// Creates signer from hex-encoded secret key.
+    pub fn from_secret(secret: &str) -> Result<Self, String> {
+        let secret = secret.trim().trim_left_matches("0x").parse()
+            .map_err(|e| format!("Invalid secret key: {:?}", e))?;
+        Self::new(secret)
+    }
+
+    /// Creates signer from an encrypted JSON key file (as created by Parity or Geth).
+    pub fn from_keystore<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut content = String::new();
+        fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("Unable to read key file {}: {}", path.display(), e))?;
+
+        let key_file: Value = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid key file {}: {}", path.display(), e))?;
+        // Geth uses capitalized field name.
+        let crypto = key_file.get("crypto").or_else(|| key_file.get("Crypto"))
+            .ok_or_else(|| format!("Invalid key file {}: missing crypto.", path.display()))?;
+        let crypto: Crypto = crypto.to_string().parse()
+            .map_err(|e| format!("Invalid key file {}: {:?}", path.display(), e))?;
+        let secret = crypto.secret(password)
+            .map_err(|e| format!("Unable to decrypt key file {}: {:?}", path.display(), e))?;
+
+        Self::new(secret)
+    }
+
+    fn new(secret: Secret) -> Result<Self, String> {
+        let address = KeyPair::from_secret(secret.clone())
+            .map_err(|e| format!("Invalid secret key: {:?}", e))?
+            .address();
+        Ok(Signer { secret, address })
+    }
+
+    /// Returns address of the signer.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Signs the transaction with replay protection (EIP-155) for given chain.
+    pub fn sign(&self, request: TransactionRequest, chain_id: u64) -> Transaction {
+        let action = request.action();
+        let transaction = UnsignedTransaction {
+            nonce: request.nonce,
+            gas_price: request.gas_price,
+            gas: request.gas,
+            action,
+            value: request.value,
+            data: request.data,
+        };
+
+        transaction.sign(&self.secret, Some(chain_id)).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethcore::transaction::{SignedTransaction, UnverifiedTransaction};
+    use rlp::UntrustedRlp;
+    use types::Address;
+    use super::{Signer, TransactionRequest};
+
+    #[test]
+    fn should_sign_with_replay_protection() {
+        let signer = Signer::from_secret("0x4646464646464646464646464646464646464646464646464646464646464646").unwrap();
+        let request = TransactionRequest {
+            to: Some(Address::from(0x35)),
+            gas: 21_000.into(),
+            ..Default::default()
+        };
+
+        let transaction = signer.sign(request, 1);
+        let decoded: UnverifiedTransaction = UntrustedRlp::new(transaction.rlp()).as_val().unwrap();
+        assert_eq!(decoded.chain_id(), Some(1));
+        assert_eq!(SignedTransaction::new(decoded).unwrap().sender(), signer.address());
+        assert_eq!(transaction.sender(), &signer.address());
+    }
+}
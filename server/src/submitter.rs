@@ -1,109 +1,406 @@
 //! Submits transactions to "edge nodes" when a block is mined.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{mem, thread};
+use std::time::Duration;
 
 use futures::future::{self, Either};
-use futures::sync::mpsc;
+use futures::sync::{mpsc, oneshot};
 use futures::{Sink as FutureSink, Future, Poll, Stream, Async};
 use web3::transports;
-use web3::{Error, Web3, Transport};
+use parking_lot::Mutex;
+use web3::{Error, ErrorKind, Web3, Transport};
 
-use database::Database;
-use types::{BlockNumber, Transaction};
-use TransportType;
+use blockchain::Blockchain;
+use database::{Claimed, Database, Drained};
+use gas_price::{self, GasPriceOracle};
+use notify::Notify;
+use transport::{self, Endpoint};
+use types::{BlockNumber, U256};
+
+/// Submission queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Queue {
+    /// Block-number based queue.
+    #[serde(rename="block")]
+    Block,
+    /// Timestamp based queue.
+    #[serde(rename="time")]
+    Timestamp,
+}
+
+/// Timestamp reserved for transactions forced by the operator.
+///
+/// Transactions stored in the timestamp queue under this target are submitted even if the queue is paused.
+pub const FORCED: BlockNumber = 0;
+
+/// Runtime control of running submitters.
+///
+/// Allows to pause submission, to replace the nodes transactions are submitted to
+/// and to shut the submitters down.
+#[derive(Debug, Default)]
+pub struct Control {
+    block_paused: AtomicBool,
+    timestamp_paused: AtomicBool,
+    halted: AtomicBool,
+    shutdown: AtomicBool,
+    transports: Mutex<Option<Arc<Vec<Endpoint>>>>,
+    changes: Arc<Notify>,
+}
+
+impl Control {
+    /// Pauses or resumes submission for given queue.
+    pub fn set_paused(&self, queue: Queue, paused: bool) {
+        info!("Submission of {:?} queue {}.", queue, if paused { "paused" } else { "resumed" });
+        self.flag(queue).store(paused, Ordering::SeqCst);
+        self.changes.notify();
+    }
+
+    /// Returns true if submission for given queue is paused.
+    pub fn is_paused(&self, queue: Queue) -> bool {
+        self.flag(queue).load(Ordering::SeqCst)
+    }
+
+    /// Halts or restores submission of all transactions (including forced ones).
+    ///
+    /// Used when the nodes are not connected to the expected chain.
+    pub fn set_halted(&self, halted: bool) {
+        self.halted.store(halted, Ordering::SeqCst);
+        self.changes.notify();
+    }
+
+    /// Returns true if submission is halted.
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+    }
+
+    /// Requests shutdown of the submitters and the blockchain updater.
+    ///
+    /// Transactions being drained are either submitted or left unacknowledged in the database.
+    pub fn shutdown(&self) {
+        info!("Shutting down submitters.");
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.changes.notify();
+    }
+
+    /// Returns true if shutdown was requested.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Replaces the nodes transactions are submitted to.
+    ///
+    /// Submitters switch to the new nodes before submitting next transactions,
+    /// transactions already passed to previous nodes are submitted in background.
+    /// The nodes should be checked with `verify_transports` first.
+    pub fn set_transports(&self, types: Vec<Endpoint>) {
+        info!("Switching submission to {} nodes.", types.len());
+        *self.transports.lock() = Some(Arc::new(types));
+        self.changes.notify();
+    }
+
+    /// Returns a notification triggered on any change affecting the timestamp submitter.
+    ///
+    /// Should also be used by the timestamp `Database` (see `Database::with_changes`).
+    pub fn changes(&self) -> Arc<Notify> {
+        self.changes.clone()
+    }
+
+    fn transports(&self) -> Option<Arc<Vec<Endpoint>>> {
+        self.transports.lock().clone()
+    }
+
+    fn flag(&self, queue: Queue) -> &AtomicBool {
+        match queue {
+            Queue::Block => &self.block_paused,
+            Queue::Timestamp => &self.timestamp_paused,
+        }
+    }
+}
 
 /// Spawns given number of transports and runs a submitter.
 /// Each transport will receive the same set of transactions.
 /// This method listens for incoming block numbers and
 /// submits all transactions scheduled for given block.
 ///
-/// This method blocks until block subscription is over.
-pub fn run_block<I: Iterator<Item=TransportType>>(
+/// If `recheck` is provided transactions priced below current minimal gas price
+/// are not submitted, but moved to `underpriced` database instead (under their original block).
+///
+/// This method blocks until block subscription is over (see `Control::shutdown`)
+/// and all transactions passed to the transports are submitted.
+pub fn run_block<I: Iterator<Item=Endpoint>>(
     types: I,
     listener: mpsc::Receiver<BlockNumber>,
     block_db: Arc<Database>,
+    blockchain: Arc<Blockchain>,
+    control: Arc<Control>,
+    recheck: Option<Arc<GasPriceOracle>>,
+    underpriced: Arc<Database>,
     submit_earlier: u64,
 ) -> Result<(), Error> {
-    let (sinks, _eloops) = init_transports(types)?;
-    let db = block_db.clone();
-    listener
-        .map(move |block| block + submit_earlier)
-        .filter(move |block| db.has(block))
-        .for_each(move |block| {
-            debug!("Sending transactions for block: {}", block);
-            match block_db.drain(block) {
-                Ok(Some(iterator)) => Either::A(Submitter::new(sinks.clone(), iterator)),
-                Ok(None) => {
-                    warn!("No transactions found in block: {}", block);
-                    Either::B(future::ok(()))
-                }
-                Err(err) => {
-                    error!("Unable to read transactions for block {}: {:?}", block, err);
-                    Either::B(future::ok(()))
-                }
-            }
-        })
-        .wait()
-        .map_err(|_| unreachable!())
+    let mut transports = Transports::new(Arc::new(types.collect()))?;
+    let result = {
+        let current = &mut transports;
+        let db = block_db.clone();
+        listener
+            .map(move |block| block + submit_earlier)
+            .filter({
+                let control = control.clone();
+                move |block| !control.is_halted() && !control.is_paused(Queue::Block) && db.has(block)
+            })
+            .for_each(move |block| {
+                debug!("Sending transactions for block: {}", block);
+                current.update(&control);
+                let sinks = current.sinks.clone();
+                let (block_db, blockchain) = (block_db.clone(), blockchain.clone());
+                let (control, underpriced) = (control.clone(), underpriced.clone());
+                // The price is resolved before draining, so that the stream is not blocked.
+                minimum_gas_price(&recheck).and_then(move |minimum| match block_db.drain(block) {
+                    Ok(Some(iterator)) => {
+                        let iterator = Interruptible::new(iterator, control)
+                            .filter(move |tx| is_priced(minimum, &underpriced, tx))
+                            .inspect(move |tx| blockchain.note_submitted(tx.transaction()));
+                        Either::A(Submitter::new(sinks, iterator))
+                    },
+                    Ok(None) => {
+                        warn!("No transactions found in block: {}", block);
+                        Either::B(future::ok(()))
+                    }
+                    Err(err) => {
+                        error!("Unable to read transactions for block {}: {:?}", block, err);
+                        Either::B(future::ok(()))
+                    }
+                })
+            })
+            .wait()
+            .map_err(|_| unreachable!())
+    };
+
+    transports.flush();
+    result
 }
 
 /// Spawns given number of transports and runs a submitter.
 /// Each transport will receive the same set of transactions.
-/// This method periodically submits all transactions
-/// scheduled for current time.
+/// This method submits all transactions scheduled for current time (in milliseconds)
+/// and transactions forced by the operator (see `FORCED`).
 ///
-/// This method blocks until block subscription is over.
-pub fn run_timestamp<I: Iterator<Item=TransportType>>(
+/// The submitter sleeps until the earliest scheduled timestamp and is woken up
+/// by `Control::changes` (which should also be notified by `timestamp_db`).
+///
+/// Underpriced transactions are handled the same way as in `run_block`.
+///
+/// This method blocks until shutdown is requested via `Control`
+/// and all transactions passed to the transports are submitted.
+pub fn run_timestamp<I: Iterator<Item=Endpoint>>(
     types: I,
     timestamp_db: Arc<Database>,
+    blockchain: Arc<Blockchain>,
+    control: Arc<Control>,
+    recheck: Option<Arc<GasPriceOracle>>,
+    underpriced: Arc<Database>,
 ) -> Result<(), Error> {
-    let (sinks, _eloops) = init_transports(types)?;
+    let mut transports = Transports::new(Arc::new(types.collect()))?;
+    let changes = control.changes();
 
     loop {
-        let time = ::time::now_utc().to_timespec().sec as u64;
-        match timestamp_db.drain(time) {
-            Ok(Some(iterator)) => {
-                debug!("Sending transactions for time: {}", time);
-                Submitter::new(sinks.clone(), iterator).wait()
+        transports.update(&control);
+        let shutdown = control.is_shutting_down();
+        if control.is_halted() {
+            // Forced transactions stay in the database (see `FORCED`) and are submitted after restart.
+            if shutdown {
+                break;
+            }
+            changes.wait(None);
+            continue;
+        }
+
+        if shutdown || ::std::thread::panicking() {
+            break;
+        }
+
+        let paused = control.is_paused(Queue::Timestamp);
+        let time = now_millis();
+        // Forced transactions are submitted even if the queue is paused.
+        let until = if paused { FORCED } else { time };
+        let drained = if timestamp_db.has(&until) {
+            let minimum = minimum_gas_price(&recheck).wait().expect("Errors are handled by minimum_gas_price; qed");
+            timestamp_db.drain(until).map(|drained| drained.map(|iterator| (minimum, iterator)))
+        } else {
+            Ok(None)
+        };
+        match drained {
+            Ok(Some((minimum, iterator))) => {
+                debug!("Sending transactions for time: {}", until);
+                let iterator = Interruptible::new(iterator, control.clone())
+                    .filter(|tx| is_priced(minimum, &underpriced, tx))
+                    .inspect(|tx| blockchain.note_submitted(tx.transaction()));
+                Submitter::new(transports.sinks.clone(), iterator).wait()
                     .expect("Submitter is never returning error; qed");
             }
             Err(err) => {
-                error!("Unable to read transactions for timestamp {}: {:?}", time, err);
+                error!("Unable to read transactions for timestamp {}: {:?}", until, err);
             },
             _ => {}
         }
 
-        if ::std::thread::panicking() {
-            break;
+        // Sleep until the next scheduled timestamp or until something changes.
+        let timeout = match timestamp_db.earliest() {
+            Some(earliest) if !paused => Some(Duration::from_millis(earliest.saturating_sub(now_millis()))),
+            _ => None,
+        };
+        trace!("Waiting for next timestamp: {:?}", timeout);
+        changes.wait(timeout);
+    }
+
+    transports.flush();
+    Ok(())
+}
+
+/// Waits until all transactions passed to the transports are submitted.
+///
+/// All senders of the transports have to be dropped before.
+fn flush(done: Vec<oneshot::Receiver<()>>) {
+    info!("Waiting for in-flight submissions.");
+    let _ = future::join_all(done).wait();
+}
+
+/// Drains the database until shutdown is requested.
+///
+/// Remaining transactions are not acknowledged, so they are restored on restart.
+struct Interruptible {
+    iterator: Drained,
+    control: Arc<Control>,
+}
+
+impl Interruptible {
+    fn new(iterator: Drained, control: Arc<Control>) -> Self {
+        Interruptible { iterator, control }
+    }
+}
+
+impl Iterator for Interruptible {
+    type Item = Claimed;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.control.is_shutting_down() {
+            info!("Leaving remaining transactions to submit after restart.");
+            return None;
+        }
+
+        self.iterator.next()
+    }
+}
+
+/// Returns current unix time in milliseconds.
+fn now_millis() -> u64 {
+    let now = ::time::now_utc().to_timespec();
+    now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+}
+
+/// Resolves current minimal gas price if transactions should be re-checked.
+///
+/// Transactions are submitted without re-checking if the price can't be computed.
+fn minimum_gas_price(recheck: &Option<Arc<GasPriceOracle>>) -> Box<Future<Item=Option<U256>, Error=()> + Send> {
+    match *recheck {
+        Some(ref oracle) => Box::new(oracle.minimum().then(|result| match result {
+            Ok(minimum) => Ok(Some(minimum)),
+            Err(err) => {
+                warn!("Unable to compute minimal gas price: {:?}", err);
+                Ok(None)
+            },
+        })),
+        None => Box::new(future::ok(None)),
+    }
+}
+
+/// Returns true if the transaction should be submitted.
+/// Underpriced transactions are moved to `underpriced` database under their original target
+/// (if that fails they are left unacknowledged, so they are re-checked after restart).
+fn is_priced(minimum: Option<U256>, underpriced: &Database, claimed: &Claimed) -> bool {
+    let minimum = match minimum {
+        Some(minimum) => minimum,
+        None => return true,
+    };
+    let transaction = claimed.transaction();
+    if gas_price::is_priced(transaction, minimum) {
+        return true;
+    }
+
+    warn!("[{:?}] Skipping underpriced transaction.", transaction.hash());
+    match underpriced.insert(claimed.target(), transaction.clone()) {
+        Ok(()) => claimed.acknowledge(),
+        Err(err) => warn!("[{:?}] Unable to store underpriced transaction: {:?}", transaction.hash(), err),
+    }
+    false
+}
+
+/// Sinks of the nodes transactions are submitted to.
+struct Transports {
+    types: Arc<Vec<Endpoint>>,
+    sinks: Vec<mpsc::Sender<Claimed>>,
+    done: Vec<oneshot::Receiver<()>>,
+    _eloops: Vec<transports::EventLoopHandle>,
+}
+
+impl Transports {
+    fn new(types: Arc<Vec<Endpoint>>) -> Result<Self, Error> {
+        let (sinks, done, eloops) = init_transports(types.iter().cloned())?;
+        Ok(Transports { types, sinks, done, _eloops: eloops })
+    }
+
+    /// Switches to the nodes set in `Control` (if changed).
+    ///
+    /// Previous transports finish in-flight submissions in background.
+    fn update(&mut self, control: &Control) {
+        let types = match control.transports() {
+            Some(ref types) if Arc::ptr_eq(types, &self.types) => return,
+            Some(types) => types,
+            None => return,
+        };
+
+        match Transports::new(types.clone()) {
+            Ok(transports) => {
+                let previous = mem::replace(self, transports);
+                thread::spawn(move || previous.flush());
+            },
+            // Connecting is retried before submitting next transactions.
+            Err(err) => error!("Unable to connect to new submission nodes: {:?}. Keeping previous ones.", err),
         }
+    }
 
-        ::std::thread::sleep(::std::time::Duration::from_secs(1))
+    /// Waits until all transactions passed to the transports are submitted.
+    fn flush(self) {
+        let Transports { sinks, done, _eloops, .. } = self;
+        drop(sinks);
+        flush(done);
     }
+}
 
+/// Checks that transactions can be submitted to given nodes (connections are closed afterwards).
+pub fn verify_transports(types: &[Endpoint]) -> Result<(), Error> {
+    for endpoint in types {
+        transport::connect(endpoint)?;
+    }
     Ok(())
 }
 
-fn init_transports<I: Iterator<Item=TransportType>>(mut types: I) 
-    -> Result<(Vec<mpsc::Sender<Transaction>>, Vec<transports::EventLoopHandle>), Error>
+fn init_transports<I: Iterator<Item=Endpoint>>(mut types: I)
+    -> Result<(Vec<mpsc::Sender<Claimed>>, Vec<oneshot::Receiver<()>>, Vec<transports::EventLoopHandle>), Error>
 {
     let mut sinks = Vec::new();
+    let mut done = Vec::new();
     let mut eloops = Vec::new();
-    while let Some(typ) = types.next() {
-        let (sink, eloop) = match typ {
-            TransportType::Ipc(path) => {
-                let (eloop, ipc) = transports::ipc::Ipc::new(&path)?;
-                (Sink::new_sink(&eloop, ipc), eloop)
-            },
-            TransportType::Http(url) => {
-                let (eloop, http) = transports::http::Http::new(&url)?;
-                (Sink::new_sink(&eloop, http), eloop)
-            }
-        };
+    while let Some(endpoint) = types.next() {
+        let (eloop, transport) = transport::connect(&endpoint)?;
+        let (sink, finished) = Sink::new_sink(&eloop, transport);
         sinks.push(sink);
+        done.push(finished);
         eloops.push(eloop);
     }
 
-    Ok((sinks, eloops))
+    Ok((sinks, done, eloops))
 }
 
 /// A sink for transactions that should be submitted to the network.
@@ -112,38 +409,68 @@ struct Sink<T> {
 }
 
 impl<T: Transport + Send + 'static> Sink<T> {
-    pub fn new_sink(eloop: &transports::EventLoopHandle, transport: T) -> mpsc::Sender<Transaction> {
+    /// Returns a sender of transactions and a receiver notified when all sent transactions are submitted
+    /// (after all senders are dropped).
+    pub fn new_sink(eloop: &transports::EventLoopHandle, transport: T) -> (mpsc::Sender<Claimed>, oneshot::Receiver<()>) {
         let (tx, rx) = mpsc::channel(1024);
-        Self::run(eloop, transport, rx);
-        tx
+        let (done_tx, done_rx) = oneshot::channel();
+        Self::run(eloop, transport, rx, done_tx);
+        (tx, done_rx)
     }
 
     fn run(
         eloop: &transports::EventLoopHandle,
         transport: T,
-        receiver: mpsc::Receiver<Transaction>,
+        receiver: mpsc::Receiver<Claimed>,
+        done: oneshot::Sender<()>,
     ) {
         let web3 = Web3::new(transport);
 
         info!("Waiting for transactions to submit...");
-        eloop.remote().spawn(move |_| receiver.for_each(move |transaction| {
-            debug!("[{:?}] Sending transaction from: {:?}", transaction.hash(), transaction.sender());
-            let hash = *transaction.hash();
-            web3.eth().send_raw_transaction(transaction.rlp().into())
+        eloop.remote().spawn(move |_| receiver.for_each(move |claimed| {
+            let hash = *claimed.transaction().hash();
+            debug!("[{:?}] Sending transaction from: {:?}", hash, claimed.transaction().sender());
+            let rlp = claimed.transaction().rlp().to_vec();
+            web3.eth().send_raw_transaction(rlp.into())
                 .then(move |res| {
                     match res {
-                        Ok(hash) => debug!("[{:?}] Submitted transaction.", hash),
-                        Err(err) => warn!("[{:?}] Error submitting: {:?}.", hash, err),
+                        Ok(hash) => {
+                            debug!("[{:?}] Submitted transaction.", hash);
+                            claimed.acknowledge();
+                        },
+                        Err(err) => match *err.kind() {
+                            // The node has seen the transaction, re-submitting won't change the result.
+                            ErrorKind::Rpc(ref err) if is_permanent_rejection(&err.message) => {
+                                warn!("[{:?}] Transaction rejected: {:?}.", hash, err);
+                                claimed.acknowledge();
+                            },
+                            // Unacknowledged transactions are restored (and re-submitted) on restart.
+                            _ => warn!("[{:?}] Error submitting: {:?}.", hash, err),
+                        },
                     }
                     Ok(())
                 })
-        }))
+        }).then(move |_| done.send(())))
     }
 }
 
+/// Returns true if the node rejected the transaction because it's already known or mined,
+/// i.e. submitting it again would be rejected as well.
+fn is_permanent_rejection(message: &str) -> bool {
+    const PERMANENT: &[&str] = &[
+        "already known",
+        "known transaction",
+        "already imported",
+        "nonce too low",
+        "nonce is too low",
+    ];
+    let message = message.to_lowercase();
+    PERMANENT.iter().any(|reason| message.contains(reason))
+}
+
 type Sending = Future<
-    Item=Vec<mpsc::Sender<Transaction>>,
-    Error=mpsc::SendError<Transaction>,
+    Item=Vec<mpsc::Sender<Claimed>>,
+    Error=mpsc::SendError<Claimed>,
 >;
 /// Submits next transaction from the iterator to all sinks.
 struct Submitter<I> {
@@ -151,13 +478,13 @@ struct Submitter<I> {
     iterator: I,
 }
 
-impl<I: Iterator<Item=Transaction>> Submitter<I> {
+impl<I: Iterator<Item=Claimed>> Submitter<I> {
     pub fn new(
-        sinks: Vec<mpsc::Sender<Transaction>>,
+        sinks: Vec<mpsc::Sender<Claimed>>,
         mut iterator: I,
     ) -> Self {
         if let Some(next) = iterator.next() {
-            debug!("[{:?}] Sending to {} endpoints.", next.hash(), sinks.len());
+            debug!("[{:?}] Sending to {} endpoints.", next.transaction().hash(), sinks.len());
             Submitter {
                 state: Some(Box::new(
                     future::join_all(sinks.into_iter().map(move |sink| sink.send(next.clone())))
@@ -173,7 +500,7 @@ impl<I: Iterator<Item=Transaction>> Submitter<I> {
     }
 }
 
-impl<I: Iterator<Item=Transaction>> Future for Submitter<I> {
+impl<I: Iterator<Item=Claimed>> Future for Submitter<I> {
     type Item = ();
     type Error = ();
 
@@ -187,7 +514,7 @@ impl<I: Iterator<Item=Transaction>> Future for Submitter<I> {
                     }));
 
                     self.iterator.next().map(move |next| {
-                        debug!("[{:?}] Sending to {} endpoints.", next.hash(), sinks.len());
+                        debug!("[{:?}] Sending to {} endpoints.", next.transaction().hash(), sinks.len());
                         Box::new(
                             future::join_all(sinks.into_iter().map(move |sink| sink.send(next.clone())))
                         ) as Box<Sending>
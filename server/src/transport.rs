@@ -0,0 +1,292 @@
+//! Transports of the nodes (selected by URL scheme) with per-node connection options.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use std::fmt;
+
+use futures::future::{self, Either};
+use futures::sync::{mpsc, oneshot};
+use futures::{Future, Stream};
+use hyper::{self, header};
+use hyper_tls::HttpsConnector;
+use serde_json;
+use tokio_timer::Timer;
+use web3::{self, ErrorKind, RequestId, Transport, helpers, rpc, transports};
+
+use TransportType;
+
+/// Request timeout (in seconds) used if the node doesn't configure one.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Number of threads resolving DNS names of HTTP nodes (per node).
+const DNS_THREADS: usize = 1;
+
+lazy_static! {
+    /// Timer of request timeouts shared by all transports (each timer runs a thread).
+    static ref TIMER: Timer = Timer::default();
+}
+
+/// Per-node connection options.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeOptions {
+    /// Request timeout (`DEFAULT_TIMEOUT_SECS` if `None`).
+    pub timeout: Option<Duration>,
+    /// Additional HTTP headers (e.g. `Authorization: Bearer <token>`).
+    pub headers: Vec<(String, String)>,
+    /// HTTP basic authentication (user and password).
+    pub basic_auth: Option<(String, String)>,
+}
+
+/// A node to connect to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    /// Transport type and address.
+    pub transport: TransportType,
+    /// Connection options.
+    pub options: NodeOptions,
+}
+
+impl Endpoint {
+    /// Creates endpoint with transport selected by URL scheme (`http(s)://`, `ws(s)://` or `ipc:///path`).
+    ///
+    /// Headers and basic authentication are supported only for HTTP nodes.
+    pub fn new(url: &str, options: NodeOptions) -> Result<Self, String> {
+        let transport = TransportType::from_url(url)?;
+        match transport {
+            TransportType::Http(_) => {},
+            _ if !options.headers.is_empty() || options.basic_auth.is_some() => {
+                return Err(format!("Headers and basic auth are supported only for HTTP nodes: {}", url));
+            },
+            _ => {},
+        }
+        Ok(Endpoint { transport, options })
+    }
+}
+
+impl From<TransportType> for Endpoint {
+    fn from(transport: TransportType) -> Self {
+        Endpoint {
+            transport,
+            options: Default::default(),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.transport {
+            TransportType::Ipc(ref path) => write!(f, "ipc://{}", path),
+            TransportType::Http(ref url) | TransportType::Ws(ref url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Returns the timer shared by all transports.
+pub fn timer() -> Timer {
+    TIMER.clone()
+}
+
+/// Connects to the node. The transport is valid as long as the event loop handle is kept.
+pub fn connect(endpoint: &Endpoint) -> Result<(transports::EventLoopHandle, NodeTransport), web3::Error> {
+    let (eloop, kind) = match endpoint.transport {
+        TransportType::Ipc(ref path) => {
+            let (eloop, ipc) = transports::ipc::Ipc::new(path)?;
+            (eloop, Kind::Ipc(ipc))
+        },
+        TransportType::Ws(ref url) => {
+            let (eloop, ws) = transports::ws::WebSocket::new(url)?;
+            (eloop, Kind::Ws(ws))
+        },
+        TransportType::Http(ref url) => {
+            let (eloop, http) = Http::new(url, &endpoint.options)?;
+            (eloop, Kind::Http(http))
+        },
+    };
+
+    Ok((eloop, NodeTransport {
+        kind,
+        timeout: endpoint.options.timeout.unwrap_or_else(|| Duration::from_secs(DEFAULT_TIMEOUT_SECS)),
+    }))
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Http(Http),
+    Ipc(transports::ipc::Ipc),
+    Ws(transports::ws::WebSocket),
+}
+
+/// Transport of any supported type (enforcing request timeout).
+#[derive(Debug, Clone)]
+pub struct NodeTransport {
+    kind: Kind,
+    timeout: Duration,
+}
+
+impl Transport for NodeTransport {
+    type Out = Box<Future<Item=rpc::Value, Error=web3::Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        match self.kind {
+            Kind::Http(ref http) => http.prepare(method, params),
+            Kind::Ipc(ref ipc) => ipc.prepare(method, params),
+            Kind::Ws(ref ws) => ws.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let response: Self::Out = match self.kind {
+            Kind::Http(ref http) => http.send(id, request),
+            Kind::Ipc(ref ipc) => Box::new(ipc.send(id, request)),
+            Kind::Ws(ref ws) => Box::new(ws.send(id, request)),
+        };
+
+        let timeout = self.timeout;
+        Box::new(response.select2(TIMER.sleep(timeout)).then(move |result| match result {
+            Ok(Either::A((value, _))) => Ok(value),
+            Err(Either::A((err, _))) => Err(err),
+            Ok(Either::B(_)) | Err(Either::B(_)) => {
+                Err(ErrorKind::Transport(format!("Request timed out after {}s", timeout.as_secs())).into())
+            },
+        }))
+    }
+}
+
+type Pending = oneshot::Sender<Result<hyper::Chunk, web3::Error>>;
+
+/// HTTP(S) transport sending additional headers with every request.
+///
+/// Used instead of web3's HTTP transport, which supports neither TLS nor custom headers.
+#[derive(Clone)]
+struct Http {
+    id: Arc<AtomicUsize>,
+    url: hyper::Uri,
+    headers: hyper::Headers,
+    write_sender: mpsc::UnboundedSender<(hyper::Request, Pending)>,
+}
+
+impl fmt::Debug for Http {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Headers are omitted, they usually contain credentials.
+        f.debug_struct("Http").field("url", &self.url).finish()
+    }
+}
+
+impl Http {
+    fn new(url: &str, options: &NodeOptions) -> Result<(transports::EventLoopHandle, Self), web3::Error> {
+        let url: hyper::Uri = url.parse()
+            .map_err(|e| web3::Error::from(ErrorKind::Transport(format!("Invalid URL {}: {:?}", url, e))))?;
+
+        let mut headers = hyper::Headers::new();
+        for &(ref name, ref value) in &options.headers {
+            headers.set_raw(name.clone(), value.clone());
+        }
+        if let Some((ref username, ref password)) = options.basic_auth {
+            headers.set(header::Authorization(header::Basic {
+                username: username.clone(),
+                password: Some(password.clone()),
+            }));
+        }
+
+        transports::EventLoopHandle::spawn(move |handle| {
+            let connector = HttpsConnector::new(DNS_THREADS, handle)
+                .map_err(|e| web3::Error::from(ErrorKind::Transport(format!("Unable to initialize TLS: {:?}", e))))?;
+            let client = hyper::Client::configure().connector(connector).build(handle);
+            let (write_sender, write_receiver) = mpsc::unbounded::<(hyper::Request, Pending)>();
+            let spawner = handle.clone();
+            handle.spawn(write_receiver.for_each(move |(request, pending)| {
+                let response = client.request(request)
+                    .and_then(|response| {
+                        let status = response.status();
+                        response.body().concat2().map(move |body| (status, body))
+                    })
+                    .then(move |result| {
+                        let result = match result {
+                            Ok((status, body)) if status.is_success() => Ok(body),
+                            Ok((status, _)) => Err(ErrorKind::Transport(format!("Unexpected response status: {}", status)).into()),
+                            Err(err) => Err(ErrorKind::Transport(format!("{:?}", err)).into()),
+                        };
+                        let _ = pending.send(result);
+                        Ok(())
+                    });
+                spawner.spawn(response);
+                Ok(())
+            }));
+
+            Ok(Http {
+                id: Default::default(),
+                url,
+                headers,
+                write_sender,
+            })
+        })
+    }
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Box<Future<Item=rpc::Value, Error=web3::Error> + Send> {
+        let body = helpers::to_string(&rpc::Request::Single(request));
+        trace!("[{}] Sending request to {}: {}", id, self.url, body);
+
+        let mut request = hyper::Request::new(hyper::Method::Post, self.url.clone());
+        {
+            let headers = request.headers_mut();
+            headers.extend(self.headers.iter());
+            headers.set(header::ContentType::json());
+            headers.set(header::ContentLength(body.len() as u64));
+        }
+        request.set_body(body);
+
+        let (pending, response) = oneshot::channel();
+        if self.write_sender.unbounded_send((request, pending)).is_err() {
+            return Box::new(future::err(ErrorKind::Transport("Event loop is not running.".into()).into()));
+        }
+
+        Box::new(response
+            .map_err(|_| web3::Error::from(ErrorKind::Transport("Event loop is not running.".into())))
+            .and_then(|result| result)
+            .and_then(|body| single_response(&body)))
+    }
+}
+
+fn single_response(body: &[u8]) -> Result<rpc::Value, web3::Error> {
+    match serde_json::from_slice(body) {
+        Ok(rpc::Response::Single(output)) => helpers::to_result_from_output(output),
+        Ok(_) => Err(ErrorKind::InvalidResponse("Expected single response, got batch.".into()).into()),
+        Err(err) => Err(ErrorKind::InvalidResponse(format!("{:?}", err)).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use TransportType;
+    use super::{Endpoint, NodeOptions};
+
+    #[test]
+    fn should_select_transport_by_url_scheme() {
+        let endpoint = |url| Endpoint::new(url, Default::default()).map(|endpoint| endpoint.transport);
+
+        assert_eq!(endpoint("http://127.0.0.1:8545"), Ok(TransportType::Http("http://127.0.0.1:8545".into())));
+        assert_eq!(endpoint("wss://node.example.com"), Ok(TransportType::Ws("wss://node.example.com".into())));
+        assert_eq!(endpoint("ws://127.0.0.1:8546"), Ok(TransportType::Ws("ws://127.0.0.1:8546".into())));
+        assert_eq!(endpoint("ipc:///tmp/jsonrpc.ipc"), Ok(TransportType::Ipc("/tmp/jsonrpc.ipc".into())));
+        assert!(endpoint("127.0.0.1:8545").is_err());
+        assert_eq!(endpoint("https://node.example.com"), Ok(TransportType::Http("https://node.example.com".into())));
+    }
+
+    #[test]
+    fn should_allow_headers_only_for_http() {
+        let options = NodeOptions {
+            headers: vec![("Authorization".into(), "Bearer token".into())],
+            ..Default::default()
+        };
+
+        assert!(Endpoint::new("http://127.0.0.1:8545", options.clone()).is_ok());
+        assert!(Endpoint::new("ws://127.0.0.1:8546", options.clone()).is_err());
+        assert!(Endpoint::new("ipc:///tmp/jsonrpc.ipc", options).is_err());
+    }
+}
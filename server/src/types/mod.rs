@@ -1,31 +1,59 @@
+//! Common types.
+
 use byteorder::{LittleEndian, ByteOrder};
-use ethcore::transaction::SignedTransaction;
-use rlp;
+use ethcore::transaction::{SignedTransaction, UnverifiedTransaction};
+use rlp::{self, UntrustedRlp};
 
 mod bytes;
 
 pub use self::bytes::Bytes;
 
+/// Block number (or timestamp in milliseconds in the timestamp queue).
 pub type BlockNumber = u64;
 
+/// Account address.
 pub type Address = ::ethcore_bigint::hash::H160;
+/// 256-bit unsigned integer.
 pub type U256 = ::ethcore_bigint::prelude::U256;
+/// 256-bit hash.
 pub type H256 = ::ethcore_bigint::hash::H256;
 
-#[derive(Debug, Clone, Deserialize)]
+/// Submission condition of a scheduled transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub enum Condition {
+	/// Submit at given block number.
 	#[serde(rename="block")]
 	Number(BlockNumber),
+	/// Submit at given unix timestamp (seconds).
 	#[serde(rename="time")]
 	Timestamp(u64),
+	/// Submit at given unix timestamp (milliseconds).
+	#[serde(rename="timeMs")]
+	TimestampMs(u64),
+}
+
+impl Condition {
+	/// Returns true for timestamp conditions and the target
+	/// (block number or timestamp in milliseconds).
+	///
+	/// Returns `None` if the timestamp can't be represented in milliseconds.
+	pub fn target(&self) -> Option<(bool, u64)> {
+		match *self {
+			Condition::Number(block_number) => Some((false, block_number)),
+			Condition::Timestamp(time) => time.checked_mul(1000).map(|time| (true, time)),
+			Condition::TimestampMs(time) => Some((true, time)),
+		}
+	}
 }
 
+/// Verified transaction with recovered sender.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Transaction {
     sender: Address,
     hash: H256,
     rlp: Vec<u8>,
+    api_key: Option<String>,
 }
 
 impl From<SignedTransaction> for Transaction {
@@ -35,59 +63,159 @@ impl From<SignedTransaction> for Transaction {
             sender: transaction.sender(),
             hash: transaction.hash(),
             rlp,
+            api_key: None,
         }
     }
 }
 
 impl Transaction {
+    /// Creates transaction from already verified parts.
     pub fn new(sender: Address, hash: H256, rlp: Vec<u8>) -> Self {
-        Transaction { sender, hash, rlp }
+        Transaction { sender, hash, rlp, api_key: None }
     }
 
+    /// Decodes signed transaction RLP and recovers the sender.
+    pub fn decode(rlp: &[u8]) -> Result<Self, String> {
+        let transaction: UnverifiedTransaction = UntrustedRlp::new(rlp).as_val()
+            .map_err(|e| format!("Invalid RLP: {:?}", e))?;
+        let transaction = SignedTransaction::new(transaction)
+            .map_err(|e| format!("Invalid signature: {:?}", e))?;
+        Ok(transaction.into())
+    }
+
+    /// Sets the id of the API key the transaction was scheduled with (see `ApiKeys::key_id`).
+    pub fn with_api_key(self, api_key: Option<String>) -> Self {
+        Transaction { api_key, ..self }
+    }
+
+    /// Returns the sender.
     pub fn sender(&self) -> &Address {
         &self.sender
     }
 
+    /// Returns the transaction hash.
     pub fn hash(&self) -> &H256 {
         &self.hash
     }
 
+    /// Returns the signed transaction RLP.
     pub fn rlp(&self) -> &[u8] {
         &self.rlp
     }
+
+    /// Returns the id of the API key the transaction was scheduled with.
+    pub fn api_key(&self) -> Option<&str> {
+        self.api_key.as_ref().map(|s| s.as_str())
+    }
 }
 
+/// Identifier of a scheduled transaction (returned by `scheduleTransaction`).
+#[derive(Debug, Clone, PartialEq)]
 pub struct TransactionId {
+    /// Transaction is in the timestamp queue.
     pub is_timestamp: bool,
+    /// The requested target was in the past, transaction is submitted immediately.
+    pub immediate: bool,
+    /// Block number or timestamp in milliseconds.
     pub num: u64,
+    /// Transaction hash.
     pub hash: H256,
+    /// Chain the transaction is scheduled on (`None` for ids issued before multi-chain support).
+    pub chain_id: Option<u64>,
 }
 
 impl TransactionId {
     const LEN: usize = 1 + 8 + 32;
-
+    const TIMESTAMP_FLAG: u8 = 1;
+    const IMMEDIATE_FLAG: u8 = 2;
+    /// Timestamp is in milliseconds (ids without the flag are in seconds).
+    const MILLIS_FLAG: u8 = 4;
+    /// Id ends with 8 bytes of chain id.
+    const CHAIN_FLAG: u8 = 8;
+
+    /// Decodes the identifier.
     pub fn from_bytes(bytes: Bytes) -> Option<Self> {
         let bytes = bytes.into_vec();
-        if bytes.len() != Self::LEN {
+        let has_chain = bytes.len() == Self::LEN + 8 && bytes[0] & Self::CHAIN_FLAG > 0;
+        if bytes.len() != Self::LEN && !has_chain {
             return None;
         }
-        let num = LittleEndian::read_u64(&bytes[1..]);
-        let hash = bytes[9..].into();
+        let is_timestamp = bytes[0] & Self::TIMESTAMP_FLAG > 0;
+        let mut num = LittleEndian::read_u64(&bytes[1..]);
+        if is_timestamp && bytes[0] & Self::MILLIS_FLAG == 0 {
+            num = match num.checked_mul(1000) {
+                Some(num) => num,
+                None => return None,
+            };
+        }
+        let hash = bytes[9..Self::LEN].into();
+        let chain_id = if has_chain { Some(LittleEndian::read_u64(&bytes[Self::LEN..])) } else { None };
     
         Some(TransactionId {
-            is_timestamp: bytes[0] > 0,
+            is_timestamp,
+            immediate: bytes[0] & Self::IMMEDIATE_FLAG > 0,
             num,
             hash,
+            chain_id,
         })
     }
 
+    /// Encodes the identifier.
     pub fn to_bytes(&self) -> Bytes {
-        let mut bytes = Vec::with_capacity(Self::LEN);
-        bytes.resize(Self::LEN, 0);
-        bytes[0] = self.is_timestamp as u8;
+        let len = if self.chain_id.is_some() { Self::LEN + 8 } else { Self::LEN };
+        let mut bytes = Vec::with_capacity(len);
+        bytes.resize(len, 0);
+        if self.is_timestamp {
+            bytes[0] |= Self::TIMESTAMP_FLAG | Self::MILLIS_FLAG;
+        }
+        if self.immediate {
+            bytes[0] |= Self::IMMEDIATE_FLAG;
+        }
         LittleEndian::write_u64(&mut bytes[1..], self.num);
-        bytes[9..].copy_from_slice(&*self.hash);
+        bytes[9..Self::LEN].copy_from_slice(&*self.hash);
+        if let Some(chain_id) = self.chain_id {
+            bytes[0] |= Self::CHAIN_FLAG;
+            LittleEndian::write_u64(&mut bytes[Self::LEN..], chain_id);
+        }
 
         bytes.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Bytes, Condition, TransactionId};
+
+    #[test]
+    fn should_encode_id_with_and_without_chain() {
+        let id = TransactionId {
+            is_timestamp: true,
+            immediate: false,
+            num: 1_500_000_000_000,
+            hash: 5.into(),
+            chain_id: Some(42),
+        };
+        let bytes = id.to_bytes();
+        assert_eq!(bytes.0.len(), 49);
+        assert_eq!(TransactionId::from_bytes(bytes), Some(id.clone()));
+
+        // Ids issued before multi-chain support remain valid.
+        let legacy = TransactionId { chain_id: None, ..id };
+        let bytes = legacy.to_bytes();
+        assert_eq!(bytes.0.len(), 41);
+        assert_eq!(TransactionId::from_bytes(bytes), Some(legacy));
+        assert_eq!(TransactionId::from_bytes(Bytes(vec![0; 45])), None);
+    }
+
+    #[test]
+    fn should_reject_timestamps_overflowing_milliseconds() {
+        assert_eq!(Condition::Timestamp(1_500_000_000).target(), Some((true, 1_500_000_000_000)));
+        assert_eq!(Condition::Timestamp(u64::max_value() / 999).target(), None);
+        assert_eq!(Condition::TimestampMs(u64::max_value()).target(), Some((true, u64::max_value())));
+
+        // Legacy ids (in seconds) that can't be converted to milliseconds.
+        let mut bytes = vec![0xff; 41];
+        bytes[0] = 1;
+        assert_eq!(TransactionId::from_bytes(Bytes(bytes)), None);
+    }
+}
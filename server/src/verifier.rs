@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use ethcore::transaction::{Action, SignedTransaction};
+use ethcore::transaction::{SignedTransaction, UnverifiedTransaction};
 use futures::{future, Future};
 use jsonrpc_core::Error;
 use rlp::UntrustedRlp;
@@ -8,8 +8,84 @@ use rlp::UntrustedRlp;
 use blockchain::Blockchain;
 use database::Database;
 use errors;
-use options::Options;
-use types::{Bytes, Transaction};
+use gas_price::GasPriceOracle;
+use options::{Options, PastTargets, SharedOptions};
+use policy::Policy;
+use rate_limit::RateLimits;
+use schedule::GasSchedule;
+use types::{Bytes, Transaction, U256};
+
+/// Checks run by `Verifier::dry_run` before the transaction is decoded.
+const REQUEST_CHECKS: &[&str] = &["target", "transaction"];
+
+/// Checks run by `Verifier::dry_run` after the transaction is decoded.
+const TRANSACTION_CHECKS: &[&str] = &[
+    "intrinsicGas", "maxGas", "gasPrice", "rules", "senderQuota", "admission", "balance", "nonce",
+];
+
+/// Returns all checks of `Verifier::dry_run` as skipped (e.g. when the request itself is rejected).
+pub fn skipped_checks() -> Vec<Check> {
+    REQUEST_CHECKS.iter().chain(TRANSACTION_CHECKS).map(|&name| Check::skipped(name)).collect()
+}
+
+/// Status of a single verification check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    /// The check passed.
+    Passed,
+    /// The check failed, the transaction would be rejected.
+    Failed,
+    /// The check could not be run (the request was rejected or the transaction couldn't be decoded).
+    Skipped,
+}
+
+/// Result of a single verification check.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    /// Name of the check.
+    pub name: &'static str,
+    /// Status of the check.
+    pub status: CheckStatus,
+    /// Error that `scheduleTransaction` would return.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+impl Check {
+    /// Creates a passed or failed check from the result.
+    pub fn new(name: &'static str, result: Result<(), Error>) -> Self {
+        let (status, error) = match result {
+            Ok(()) => (CheckStatus::Passed, None),
+            Err(err) => (CheckStatus::Failed, Some(err)),
+        };
+        Check { name, status, error }
+    }
+
+    /// Creates a skipped check.
+    pub fn skipped(name: &'static str) -> Self {
+        Check { name, status: CheckStatus::Skipped, error: None }
+    }
+}
+
+/// Result of a dry-run verification.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// Whether the transaction would be accepted (all checks passed).
+    pub accepted: bool,
+    /// Results of all checks.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Creates a report from results of the checks.
+    pub fn new(checks: Vec<Check>) -> Self {
+        Report {
+            accepted: checks.iter().all(|check| check.status == CheckStatus::Passed),
+            checks,
+        }
+    }
+}
 
 #[derive(Debug)]
 enum VerifierMode {
@@ -21,16 +97,20 @@ enum VerifierMode {
 ///
 /// It should:
 /// - do ecrecover to extract sender
-/// - check if sender is certified
+/// - enforce per-sender rate limits
+/// - check if sender is allowed by admission policy
 /// - validate block number (if it's in the future not past)
 /// - validate minimal gas requirements
-/// - force minimal gas price (hardcoded)
+/// - force minimal gas price (according to gas price policy)
 /// - validate sender balance and nonce
 #[derive(Debug)]
 pub struct Verifier {
     blockchain: Arc<Blockchain>,
     database: Arc<Database>,
-    options: Options,
+    limits: Arc<RateLimits>,
+    policy: Arc<Policy>,
+    gas_price: Arc<GasPriceOracle>,
+    options: Arc<SharedOptions>,
     mode: VerifierMode,
 }
 
@@ -39,34 +119,41 @@ impl Verifier {
     pub fn new_block(
         blockchain: Arc<Blockchain>,
         database: Arc<Database>,
-        options: Options,
+        limits: Arc<RateLimits>,
+        policy: Arc<Policy>,
+        gas_price: Arc<GasPriceOracle>,
+        options: Arc<SharedOptions>,
     ) -> Self {
-        Verifier { blockchain, database, options, mode: VerifierMode::Block, }
+        Verifier { blockchain, database, limits, policy, gas_price, options, mode: VerifierMode::Block, }
     }
 
     /// Create new verifier for timestamp-based scheduling.
     pub fn new_timestamp(
         blockchain: Arc<Blockchain>,
         database: Arc<Database>,
-        options: Options,
+        limits: Arc<RateLimits>,
+        policy: Arc<Policy>,
+        gas_price: Arc<GasPriceOracle>,
+        options: Arc<SharedOptions>,
     ) -> Self {
-        Verifier { blockchain, database, options, mode: VerifierMode::Timestamp, }
+        Verifier { blockchain, database, limits, policy, gas_price, options, mode: VerifierMode::Timestamp, }
     }
 
     /// Verify and parse given number (block or timestamp) and RLP.
+    ///
+    /// Returns the target number (changed to current one if the requested target is in the past)
+    /// and a flag indicating if the transaction is going to be submitted immediately.
     pub fn verify(&self, num: u64, transaction: Bytes)
-        -> Box<Future<Item=(u64, Transaction), Error=Error> + Send>
+        -> Box<Future<Item=(u64, bool, Transaction), Error=Error> + Send>
     {
-        let result = match self.mode {
-            VerifierMode::Block => self.verify_block_number(num),
-            VerifierMode::Timestamp => self.verify_timestamp(num),
+        let options = self.options.get();
+        let (num, immediate) = match self.verify_target(&options, num) {
+            Ok(res) => res,
+            Err(err) => return Box::new(future::err(err)),
         };
-        if let Err(err) = result {
-            return Box::new(future::err(err));
-        }
 
         // Verify some basics about the transaction.
-        let tx = match verify_transaction(transaction, &self.options) {
+        let tx = match verify_transaction(transaction, &options, &self.gas_schedules(&options, num)) {
             Ok(tx) => tx,
             Err(err) => {
                 debug!("Rejecting request: {:?}", err);
@@ -75,123 +162,443 @@ impl Verifier {
         };
 
         let (hash, sender) = (tx.hash(), tx.sender());
+        // Limit requests before querying the node.
+        if !self.limits.check_sender(&sender) {
+            debug!("[{:?}] Rejecting. Sender exceeded rate limit: {}", hash, sender);
+            return Box::new(future::err(errors::sender_rate_limited()));
+        }
+
         // Verify transaction sender
-        if !self.database.sender_allowed(&sender) {
-            debug!("[{:?}] Rejecting. Sender already has too many transactions: {}", hash, sender);
-            return Box::new(future::err(errors::transaction("Sender already has too many transactions.")));
+        if let Err(err) = self.verify_sender_quota(&tx) {
+            return Box::new(future::err(err));
         }
 
         // Validate balance and nonce
         let blockchain = self.blockchain.clone();
-        let strict_nonce = self.options.strict_nonce;
-        Box::new(self.blockchain.is_certified(sender)
-            .map_err(errors::transaction)
-            .and_then(move |is_certified| {
-                if !is_certified {
-                    debug!("[{:?}] Rejecting. Sender not certified: {:?}", hash, sender);
-                    return future::Either::A(future::err(errors::transaction(
-                        format!("Sender is not certified.")
-                    )));
+        let strict_nonce = options.strict_nonce;
+        let count_in_flight = options.count_in_flight;
+        let gas_price = self.gas_price.minimum().map_err(errors::node);
+        Box::new(self.policy.is_allowed(sender)
+            .map_err(errors::node)
+            .join(gas_price)
+            .and_then(move |(is_allowed, min_gas_price)| {
+                if let Err(err) = verify_gas_price(&tx, min_gas_price).and_then(|_| verify_admission(&tx, is_allowed)) {
+                    return future::Either::A(future::err(err));
                 }
 
+                let state = blockchain.clone();
                 future::Either::B(blockchain.balance_and_nonce(sender)
-                    .map_err(errors::transaction)
+                    .map_err(errors::node)
                     .and_then(move |(balance, nonce)| {
-                        let required = tx.value.saturating_add(tx.gas.saturating_mul(tx.gas_price));
-                        if  balance < required {
-                            debug!("[{:?}] Rejecting. Insufficient balance: {:?} < {:?}", hash, balance, required);
-                            return Err(errors::transaction(
-                                format!("Insufficient balance (required: {}, got: {})", required, balance)
-                            ));
-                        }
-
-                        if strict_nonce && tx.nonce != nonce {
-                            debug!("[{:?}] Rejecting. Invalid nonce: {:?} != {:?}", hash, tx.nonce, nonce);
-                            return Err(errors::transaction(
-                                format!("Invalid nonce (required: {}, got: {})", nonce, tx.nonce)
-                            ));
-                        } else if !strict_nonce && tx.nonce < nonce {
-                            debug!("[{:?}] Rejecting. Invalid nonce: {:?} < {:?}", hash, tx.nonce, nonce);
-                            return Err(errors::transaction(
-                                format!("Invalid nonce (required at least: {}, got: {})", nonce, tx.nonce)
-                            ));
-                        }
-
-                        Ok((num, tx.into()))
+                        let nonce = if count_in_flight { state.next_nonce(&sender, nonce) } else { nonce };
+                        verify_balance(&tx, balance)?;
+                        verify_nonce(&tx, nonce, strict_nonce)?;
+                        Ok((num, immediate, tx.into()))
                     }))
             })
         )
     }
 
-    fn verify_block_number(&self, block_number: u64) -> Result<(), Error> {
-        let latest_block = self.blockchain.latest_block();
-        if block_number > latest_block + self.options.max_schedule_block {
-            debug!("Rejecting request. Block is too high: {} > {}", block_number, latest_block + self.options.max_schedule_block);
-            return Err(errors::block(format!(
-                "Block number is too high: {} > {}",
-                block_number,
-                latest_block + self.options.max_schedule_block,
-            )));
+    /// Runs all checks of `verify` and reports result of each of them instead of stopping at the first failure.
+    ///
+    /// Checks that require a decoded transaction are skipped if it's invalid.
+    /// Sender rate limit is not consumed and nothing is inserted to the database.
+    pub fn dry_run(&self, num: u64, transaction: Bytes) -> Box<Future<Item=Vec<Check>, Error=Error> + Send> {
+        let options = self.options.get();
+        let target = self.verify_target(&options, num);
+        let num = target.as_ref().map(|&(num, _)| num).unwrap_or(num);
+        let mut checks = vec![Check::new("target", target.map(|_| ()))];
+
+        let tx = match decode(transaction, options.chain_id) {
+            Ok(tx) => tx,
+            Err(err) => {
+                checks.push(Check::new("transaction", Err(err)));
+                checks.extend(TRANSACTION_CHECKS.iter().map(|&name| Check::skipped(name)));
+                return Box::new(future::ok(checks));
+            },
+        };
+        checks.push(Check::new("transaction", Ok(())));
+
+        let intrinsic_gas = verify_intrinsic_gas(&tx, &self.gas_schedules(&options, num));
+        let max_gas = verify_max_gas(&tx, options.max_gas);
+        let static_gas_price = verify_gas_price(&tx, options.min_gas_price.into());
+        let rules = options.rules.verify(&tx.action, &tx.data, tx.value);
+        let sender_quota = self.verify_sender_quota(&tx);
+
+        // Node errors are reported as failures of the checks relying on them.
+        let sender = tx.sender();
+        let blockchain = self.blockchain.clone();
+        let strict_nonce = options.strict_nonce;
+        let count_in_flight = options.count_in_flight;
+        let is_allowed = self.policy.is_allowed(sender).then(|res| Ok::<_, Error>(res.map_err(errors::node)));
+        let gas_price = self.gas_price.minimum().then(|res| Ok(res.map_err(errors::node)));
+        let state = self.blockchain.balance_and_nonce(sender).then(|res| Ok(res.map_err(errors::node)));
+        Box::new(is_allowed.join3(gas_price, state).map(move |(is_allowed, gas_price, state)| {
+            let state = state.map(|(balance, nonce)| {
+                let nonce = if count_in_flight { blockchain.next_nonce(&sender, nonce) } else { nonce };
+                (balance, nonce)
+            });
+
+            checks.extend(vec![
+                Check::new("intrinsicGas", intrinsic_gas),
+                Check::new("maxGas", max_gas),
+                Check::new("gasPrice", static_gas_price.and_then(|_| gas_price).and_then(|min| verify_gas_price(&tx, min))),
+                Check::new("rules", rules),
+                Check::new("senderQuota", sender_quota),
+                Check::new("admission", is_allowed.and_then(|is_allowed| verify_admission(&tx, is_allowed))),
+                Check::new("balance", state.clone().and_then(|(balance, _)| verify_balance(&tx, balance))),
+                Check::new("nonce", state.and_then(|(_, nonce)| verify_nonce(&tx, nonce, strict_nonce))),
+            ]);
+            checks
+        }))
+    }
+
+    fn verify_target(&self, options: &Options, num: u64) -> Result<(u64, bool), Error> {
+        match self.mode {
+            VerifierMode::Block => self.verify_block_number(options, num),
+            VerifierMode::Timestamp => self.verify_timestamp(options, num),
         }
+    }
 
+    /// Returns schedules of the fork active now and the fork active at the target.
+    ///
+    /// Only one coordinate of the target is known (block number or timestamp), the other one is taken
+    /// from the current state. The transaction has to be valid under both schedules, since it may be
+    /// submitted earlier than scheduled (e.g. when forced by the operator).
+    fn gas_schedules(&self, options: &Options, num: u64) -> Vec<GasSchedule> {
+        let now = ::time::now_utc().to_timespec().sec as u64;
+        let latest_block = self.blockchain.latest_block();
+        let target = match self.mode {
+            VerifierMode::Block => options.schedule.at(num, now),
+            VerifierMode::Timestamp => options.schedule.at(latest_block, num / 1000),
+        };
+        vec![options.schedule.at(latest_block, now), target]
+    }
+
+    fn verify_sender_quota(&self, tx: &SignedTransaction) -> Result<(), Error> {
+        if !self.database.sender_allowed(&tx.sender()) {
+            debug!("[{:?}] Rejecting. Sender already has too many transactions: {}", tx.hash(), tx.sender());
+            return Err(errors::too_many_transactions());
+        }
         Ok(())
     }
 
-    fn verify_timestamp(&self, time: u64) -> Result<(), Error> {
-        let current_time_seconds = ::time::now_utc().to_timespec().sec as u64;
-        if time > current_time_seconds + self.options.max_schedule_seconds {
-            debug!("Rejecting request. Timestamp is too high: {} > {}", time, current_time_seconds + self.options.max_schedule_seconds);
-            return Err(errors::timestamp(format!(
-                "Timestamp is too high: {} > {}",
-                time,
-                current_time_seconds + self.options.max_schedule_seconds,
-            )));
+    fn verify_block_number(&self, options: &Options, block_number: u64) -> Result<(u64, bool), Error> {
+        let latest_block = self.blockchain.latest_block();
+        if block_number > latest_block + options.max_schedule_block {
+            debug!("Rejecting request. Block is too high: {} > {}", block_number, latest_block + options.max_schedule_block);
+            return Err(errors::block_too_high(latest_block + options.max_schedule_block, block_number));
         }
 
-        Ok(())
+        let min_block = latest_block + 1 + options.min_lead_blocks;
+        if block_number >= min_block {
+            return Ok((block_number, false));
+        }
+
+        // Only targets that were already reached can be submitted immediately,
+        // future targets closer than minimal lead are always rejected.
+        if block_number <= latest_block && options.past_targets == PastTargets::Submit {
+            debug!("Block is in the past: {} <= {}. Submitting immediately.", block_number, latest_block);
+            return Ok((latest_block, true));
+        }
+
+        debug!("Rejecting request. Block is too low: {} < {}", block_number, min_block);
+        Err(errors::block_too_low(min_block, block_number))
+    }
+
+    /// Timestamps are in milliseconds.
+    fn verify_timestamp(&self, options: &Options, time: u64) -> Result<(u64, bool), Error> {
+        let now = ::time::now_utc().to_timespec();
+        let current_time = now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000;
+        let max_time = current_time.saturating_add(options.max_schedule_seconds.saturating_mul(1000));
+        if time > max_time {
+            debug!("Rejecting request. Timestamp is too high: {} > {}", time, max_time);
+            return Err(errors::timestamp_too_high(max_time, time));
+        }
+
+        let min_time = current_time.saturating_add(options.min_lead_seconds.saturating_mul(1000));
+        if time >= min_time {
+            return Ok((time, false));
+        }
+
+        if time <= current_time && options.past_targets == PastTargets::Submit {
+            debug!("Timestamp is in the past: {} <= {}. Submitting immediately.", time, current_time);
+            return Ok((current_time, true));
+        }
+
+        debug!("Rejecting request. Timestamp is too low: {} < {}", time, min_time);
+        Err(errors::timestamp_too_low(min_time, time))
     }
 }
 
-fn verify_transaction(transaction: Bytes, options: &Options) -> Result<SignedTransaction, Error> {
+/// Performs stateless checks of the transaction: RLP, signature and chain id,
+/// intrinsic (under all given fork schedules) and maximal gas, minimal gas price and verification rules.
+///
+/// Sender state (balance, nonce) and admission policies are checked by the `Verifier`.
+pub fn verify_transaction(transaction: Bytes, options: &Options, schedules: &[GasSchedule]) -> Result<SignedTransaction, Error> {
+    let tx = decode(transaction, options.chain_id)?;
+    verify_intrinsic_gas(&tx, schedules)?;
+    verify_max_gas(&tx, options.max_gas)?;
+    verify_gas_price(&tx, options.min_gas_price.into())?;
+
+    // Validate recipient, method and value
+    if let Err(err) = options.rules.verify(&tx.action, &tx.data, tx.value) {
+        debug!("[{:?}] Rejecting. Transaction not allowed by rules: {:?}", tx.hash(), err.message);
+        return Err(err);
+    }
+
+    Ok(tx)
+}
+
+/// Returns chain id of the transaction signature (`None` if the transaction is not replay-protected).
+pub fn chain_id(transaction: &Bytes) -> Result<Option<u64>, Error> {
+    let tx: UnverifiedTransaction = UntrustedRlp::new(&transaction.0).as_val().map_err(errors::rlp)?;
+    Ok(tx.chain_id())
+}
+
+/// Decodes the transaction and validates signature and chain id.
+fn decode(transaction: Bytes, chain_id: u64) -> Result<SignedTransaction, Error> {
     let rlp = UntrustedRlp::new(&transaction.into_vec()).as_val().map_err(errors::rlp)?;
     let tx = SignedTransaction::new(rlp).map_err(errors::transaction)?;
-    tx.verify_basic(true, Some(options.chain_id), false).map_err(errors::transaction)?;
-    // Validate basic gas
-    let minimal_gas = minimal_gas(&tx);
+    tx.verify_basic(true, Some(chain_id), false).map_err(errors::transaction)?;
+    Ok(tx)
+}
+
+/// Verifies that the gas covers intrinsic gas of the transaction under all given schedules.
+fn verify_intrinsic_gas(tx: &SignedTransaction, schedules: &[GasSchedule]) -> Result<(), Error> {
+    let minimal_gas = schedules.iter().map(|schedule| schedule.intrinsic_gas(&tx.action, &tx.data)).max().unwrap_or(0);
     if tx.gas < minimal_gas.into() {
         debug!("[{:?}] Rejecting. Gas too low: {:?} < {}", tx.hash(), tx.gas, minimal_gas);
-        return Err(errors::transaction(format!("Gas is too low. Required: {}", minimal_gas)));
+        return Err(errors::gas_too_low(minimal_gas.into(), tx.gas));
     }
+    Ok(())
+}
 
-    // Validate maximal gas
-    if tx.gas > options.max_gas.into() {
-        debug!("[{:?}] Rejecting. Gas too high: {:?} > {}", tx.hash(), tx.gas, options.max_gas);
-        return Err(errors::transaction(format!("Gas is too high. Maximal: {}", options.max_gas)));
+fn verify_max_gas(tx: &SignedTransaction, max_gas: u64) -> Result<(), Error> {
+    if tx.gas > max_gas.into() {
+        debug!("[{:?}] Rejecting. Gas too high: {:?} > {}", tx.hash(), tx.gas, max_gas);
+        return Err(errors::gas_too_high(max_gas.into(), tx.gas));
     }
+    Ok(())
+}
 
-    // Validate gas price
-    if tx.gas_price < options.min_gas_price.into() {
-        debug!("[{:?}] Rejecting. Gas price too low: {:?} < {}", tx.hash(), tx.gas_price, options.min_gas_price);
-        return Err(errors::transaction(format!("Gas price is too low. Required: {} wei", options.min_gas_price)));
+fn verify_gas_price(tx: &SignedTransaction, min_gas_price: U256) -> Result<(), Error> {
+    if tx.gas_price < min_gas_price {
+        debug!("[{:?}] Rejecting. Gas price too low: {:?} < {:?}", tx.hash(), tx.gas_price, min_gas_price);
+        return Err(errors::gas_price_too_low(min_gas_price, tx.gas_price));
     }
+    Ok(())
+}
 
-    Ok(tx)
+fn verify_admission(tx: &SignedTransaction, is_allowed: bool) -> Result<(), Error> {
+    if !is_allowed {
+        debug!("[{:?}] Rejecting. Sender not allowed: {:?}", tx.hash(), tx.sender());
+        return Err(errors::not_certified());
+    }
+    Ok(())
+}
+
+fn verify_balance(tx: &SignedTransaction, balance: U256) -> Result<(), Error> {
+    let required = tx.value.saturating_add(tx.gas.saturating_mul(tx.gas_price));
+    if balance < required {
+        debug!("[{:?}] Rejecting. Insufficient balance: {:?} < {:?}", tx.hash(), balance, required);
+        return Err(errors::insufficient_balance(required, balance));
+    }
+    Ok(())
+}
+
+fn verify_nonce(tx: &SignedTransaction, nonce: U256, strict_nonce: bool) -> Result<(), Error> {
+    if strict_nonce && tx.nonce != nonce {
+        debug!("[{:?}] Rejecting. Invalid nonce: {:?} != {:?}", tx.hash(), tx.nonce, nonce);
+        return Err(errors::invalid_nonce(nonce, tx.nonce, true));
+    } else if !strict_nonce && tx.nonce < nonce {
+        debug!("[{:?}] Rejecting. Invalid nonce: {:?} < {:?}", tx.hash(), tx.nonce, nonce);
+        return Err(errors::invalid_nonce(nonce, tx.nonce, false));
+    }
+    Ok(())
 }
 
-fn minimal_gas(tx: &SignedTransaction) -> u64 {
-    // TODO [ToDr] take from schedule?
-    const TX_CREATE_GAS: u64 = 53_000;
-    const TX_GAS: u64 = 21_000;
-    const TX_DATA_ZERO_GAS: u64 = 4;
-    const TX_DATA_NON_ZERO_GAS: u64 = 68;
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
 
-    let is_create = match tx.action {
-        Action::Create => true,
-        Action::Call(_) => false,
-    };
+    use futures::Future;
+    use jsonrpc_core::ErrorCode;
+    use tempdir::TempDir;
 
-	tx.data.iter().fold(
-        if is_create { TX_CREATE_GAS } else { TX_GAS },
-		|acc, b| acc + if *b == 0 { TX_DATA_ZERO_GAS } else { TX_DATA_NON_ZERO_GAS },
-    )
+    use blockchain::Blockchain;
+    use database::Database;
+    use errors::{self, codes};
+    use gas_price::GasPriceOracle;
+    use options::{Options, PastTargets, SharedOptions};
+    use policy::All;
+    use rate_limit::RateLimits;
+    use schedule::Schedule;
+    use sign::{Signer, TransactionRequest};
+    use transport::Endpoint;
+    use types::{Address, Bytes};
+    use super::{CheckStatus, Report, Verifier, skipped_checks};
+
+    fn options() -> Options {
+        Options {
+            chain_id: 1,
+            schedule: Schedule::for_chain(1),
+            max_gas: 1_000_000,
+            min_gas_price: 1,
+            gas_price: Default::default(),
+            max_schedule_block: 100,
+            max_schedule_seconds: 3600,
+            past_targets: Default::default(),
+            min_lead_blocks: 0,
+            min_lead_seconds: 0,
+            strict_nonce: false,
+            count_in_flight: false,
+            max_txs_per_sender: 1,
+            rules: Default::default(),
+            rpc_listen_address: "127.0.0.1:0".parse().unwrap(),
+            rpc_server_threads: 1,
+            processing_threads: 1,
+            ipc_path: None,
+            ipc_permissions: 0o600,
+            admin_token: None,
+            api_keys: Default::default(),
+            ip_rate_limit: None,
+            sender_rate_limit: None,
+            trusted_proxies: 0,
+        }
+    }
+
+    /// Creates a verifier of the block queue (the node is not reachable, so the latest block is `0`).
+    fn verifier(dir: &TempDir, options: Options) -> Verifier {
+        let limits = Arc::new(RateLimits::new(&options));
+        let options = Arc::new(SharedOptions::new(options));
+        let endpoint = Endpoint::new("http://127.0.0.1:1", Default::default()).unwrap();
+        let blockchain = Arc::new(Blockchain::new(&[endpoint], Default::default(), Default::default(), false).unwrap());
+        let gas_price = Arc::new(GasPriceOracle::new(blockchain.clone(), options.clone()));
+        let database = Database::open(dir.path(), 1).unwrap().with_options(options.clone());
+        Verifier::new_block(blockchain, Arc::new(database), limits, Arc::new(All(vec![])), gas_price, options)
+    }
+
+    fn with_lead(past_targets: PastTargets) -> Options {
+        Options {
+            past_targets,
+            min_lead_blocks: 2,
+            min_lead_seconds: 60,
+            ..options()
+        }
+    }
+
+    fn now_ms() -> u64 {
+        let now = ::time::now_utc().to_timespec();
+        now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+    }
+
+    fn statuses(report: &Report) -> Vec<(&'static str, CheckStatus)> {
+        report.checks.iter().map(|check| (check.name, check.status)).collect()
+    }
+
+    #[test]
+    fn should_skip_transaction_checks_of_invalid_transactions() {
+        let dir = TempDir::new("verifier").unwrap();
+        let report = Report::new(verifier(&dir, options()).dry_run(10, Bytes(vec![1, 2, 3])).wait().unwrap());
+
+        assert!(!report.accepted);
+        let mut expected = vec![("target", CheckStatus::Passed), ("transaction", CheckStatus::Failed)];
+        expected.extend(skipped_checks().into_iter().skip(2).map(|check| (check.name, CheckStatus::Skipped)));
+        assert_eq!(statuses(&report), expected);
+    }
+
+    #[test]
+    fn should_report_every_check_of_valid_transactions() {
+        let dir = TempDir::new("verifier").unwrap();
+        let signer = Signer::from_secret("0x4646464646464646464646464646464646464646464646464646464646464646").unwrap();
+        let transaction = signer.sign(TransactionRequest {
+            to: Some(Address::from(0x35)),
+            gas: 21_000.into(),
+            gas_price: 1.into(),
+            ..Default::default()
+        }, 1);
+
+        let checks = verifier(&dir, options()).dry_run(1_000, transaction.rlp().to_vec().into()).wait().unwrap();
+        let report = Report::new(checks);
+
+        // Target is too far and the node state can't be fetched.
+        assert!(!report.accepted);
+        assert_eq!(statuses(&report), vec![
+            ("target", CheckStatus::Failed),
+            ("transaction", CheckStatus::Passed),
+            ("intrinsicGas", CheckStatus::Passed),
+            ("maxGas", CheckStatus::Passed),
+            ("gasPrice", CheckStatus::Passed),
+            ("rules", CheckStatus::Passed),
+            ("senderQuota", CheckStatus::Passed),
+            ("admission", CheckStatus::Passed),
+            ("balance", CheckStatus::Failed),
+            ("nonce", CheckStatus::Failed),
+        ]);
+    }
+
+    #[test]
+    fn should_accept_only_if_all_checks_passed() {
+        let mut checks = skipped_checks();
+        assert!(!Report::new(checks.clone()).accepted);
+
+        for check in &mut checks {
+            check.status = CheckStatus::Passed;
+        }
+        assert!(Report::new(checks).accepted);
+        assert!(Report::new(vec![]).accepted);
+    }
+
+    #[test]
+    fn should_submit_past_blocks_and_reject_blocks_within_lead() {
+        let dir = TempDir::new("verifier").unwrap();
+        let options = with_lead(PastTargets::Submit);
+        let verifier = verifier(&dir, options.clone());
+
+        assert_eq!(verifier.verify_block_number(&options, 0).unwrap(), (0, true));
+        assert_eq!(verifier.verify_block_number(&options, 1).unwrap_err().data, errors::block_too_low(3, 1).data);
+        assert_eq!(verifier.verify_block_number(&options, 2).unwrap_err().data, errors::block_too_low(3, 2).data);
+        assert_eq!(verifier.verify_block_number(&options, 3).unwrap(), (3, false));
+    }
+
+    #[test]
+    fn should_reject_past_blocks_and_blocks_within_lead() {
+        let dir = TempDir::new("verifier").unwrap();
+        let options = with_lead(PastTargets::Reject);
+        let verifier = verifier(&dir, options.clone());
+
+        assert_eq!(verifier.verify_block_number(&options, 0).unwrap_err().data, errors::block_too_low(3, 0).data);
+        assert_eq!(verifier.verify_block_number(&options, 1).unwrap_err().data, errors::block_too_low(3, 1).data);
+        assert_eq!(verifier.verify_block_number(&options, 3).unwrap(), (3, false));
+    }
+
+    #[test]
+    fn should_submit_past_timestamps_and_reject_timestamps_within_lead() {
+        let dir = TempDir::new("verifier").unwrap();
+        let options = with_lead(PastTargets::Submit);
+        let verifier = verifier(&dir, options.clone());
+        let now = now_ms();
+
+        let (target, immediate) = verifier.verify_timestamp(&options, now - 1_000).unwrap();
+        assert!(immediate);
+        assert!(target >= now);
+        let err = verifier.verify_timestamp(&options, now + 10_000).unwrap_err();
+        assert_eq!(err.code, ErrorCode::ServerError(codes::INVALID_TIMESTAMP));
+        assert_eq!(verifier.verify_timestamp(&options, now + 120_000).unwrap(), (now + 120_000, false));
+    }
+
+    #[test]
+    fn should_reject_past_timestamps_and_timestamps_within_lead() {
+        let dir = TempDir::new("verifier").unwrap();
+        let options = with_lead(PastTargets::Reject);
+        let verifier = verifier(&dir, options.clone());
+        let now = now_ms();
+
+        let err = verifier.verify_timestamp(&options, now - 1_000).unwrap_err();
+        assert_eq!(err.code, ErrorCode::ServerError(codes::INVALID_TIMESTAMP));
+        let err = verifier.verify_timestamp(&options, now + 10_000).unwrap_err();
+        assert_eq!(err.code, ErrorCode::ServerError(codes::INVALID_TIMESTAMP));
+        assert_eq!(verifier.verify_timestamp(&options, now + 120_000).unwrap(), (now + 120_000, false));
+    }
 }